@@ -0,0 +1,67 @@
+use crate::{JobId, Pid};
+use chrono::{DateTime, Local};
+use log::error;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Process lifecycle event
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEvent {
+    Spawn,
+    Stop,
+    Restart,
+    Exit,
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    ts: DateTime<Local>,
+    event: AuditEvent,
+    job_id: JobId,
+    pid: Pid,
+    command: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+}
+
+/// Append-only file with one JSON line per process lifecycle event
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file: Mutex::new(file),
+        })
+    }
+    /// Append event. Write errors are logged only.
+    pub fn record(
+        &self,
+        event: AuditEvent,
+        job_id: JobId,
+        pid: Pid,
+        command: &[String],
+        exit_code: Option<i32>,
+    ) {
+        let entry = AuditEntry {
+            ts: Local::now(),
+            event,
+            job_id,
+            pid,
+            command,
+            exit_code,
+        };
+        let mut line = serde_json::to_string(&entry).expect("serialize");
+        line.push('\n');
+        // Single write per line, to keep lines intact
+        if let Err(e) = self.file.lock().expect("lock").write_all(line.as_bytes()) {
+            error!("Failed to write audit log: {e}");
+        }
+    }
+}
@@ -1,14 +1,29 @@
+use chrono::Local;
+use chrono_tz::Tz;
 use clap::{CommandFactory, FromArgMatches, Subcommand};
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use log::{error, info};
 use shell_compose::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::File;
+use std::io;
+use std::path::Path;
 use std::process::{self, Stdio};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{env, thread};
 
-struct DispatcherProc;
+/// Retries of refused connections to the background process
+const CONNECT_RETRIES: u32 = 3;
+
+struct DispatcherProc {
+    /// Background process running in the foreground of the terminal
+    attached: Option<process::Child>,
+}
 
 impl DispatcherProc {
-    fn spawn() -> DispatcherProc {
+    fn spawn(attach: bool) -> DispatcherProc {
         let mut exe = env::current_exe().unwrap();
         exe.set_file_name(
             exe.file_name()
@@ -33,12 +48,28 @@ impl DispatcherProc {
             // See https://stackoverflow.com/a/78989930 for a possible alternative.
         }
         if env::var("RUST_LOG").unwrap_or("".to_string()) == "debug" {
-            proc.env("RUST_LOG", "debug")
-        } else {
-            proc.stdout(Stdio::null()).stderr(Stdio::null())
+            proc.env("RUST_LOG", "debug");
+        } else if !attach {
+            proc.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        // A detached background process outlives the CLI, so it is never waited on
+        #[allow(clippy::zombie_processes)]
+        let child = proc.spawn().unwrap();
+        DispatcherProc {
+            attached: attach.then_some(child),
+        }
+    }
+    /// Wait for termination of an attached background process
+    fn wait_attached(self) {
+        let Some(mut child) = self.attached else {
+            return;
         };
-        proc.spawn().unwrap();
-        DispatcherProc
+        info!(target: "dispatcher", "Background process attached - press Ctrl-C to stop");
+        // Ctrl-C is handled by the background process, which terminates its jobs
+        if let Err(e) = ctrlc::set_handler(|| {}) {
+            error!(target: "dispatcher", "{e}");
+        }
+        let _ = child.wait();
     }
     fn wait(&self, max_ms: u64) -> Result<(), DispatcherError> {
         let mut wait_ms = 0;
@@ -53,52 +84,271 @@ impl DispatcherProc {
     }
 }
 
-fn cli() -> Result<(), DispatcherError> {
+fn cli_parser() -> clap::Command {
     let cli = Cli::command();
     let cli = ExecCommand::augment_subcommands(cli);
     let cli = CliCommand::augment_subcommands(cli);
-    let mut cli = cli.about(env!("CARGO_PKG_DESCRIPTION")); // Overwritten by augment_subcommands
-    let matches = cli.clone().get_matches();
-    let exec_command = ExecCommand::from_arg_matches(&matches);
-    let cli_command = CliCommand::from_arg_matches(&matches);
-    if exec_command.is_err() && cli_command.is_err() {
-        cli.print_help().ok();
+    cli.about(env!("CARGO_PKG_DESCRIPTION")) // Overwritten by augment_subcommands
+}
+
+fn cli() -> Result<(), DispatcherError> {
+    let matches = cli_parser().get_matches();
+    if ExecCommand::from_arg_matches(&matches).is_err()
+        && CliCommand::from_arg_matches(&matches).is_err()
+    {
+        cli_parser().print_help().ok();
         return Ok(());
     }
 
     init_cli_logger();
 
+    let attach = Cli::from_arg_matches(&matches)?.attach_daemon;
+    let Some(msg) = request_message(&matches)? else {
+        return Ok(());
+    };
+    if matches!(msg, Message::CliCommand(CliCommand::Batch)) {
+        return batch();
+    }
+    if let Message::ExecCommand(
+        cmd @ ExecCommand::Up {
+            deadline: Some(deadline),
+            ..
+        },
+    ) = &msg
+    {
+        return up_with_retry(cmd, *deadline);
+    }
+
+    let mut dispatcher = None;
     if IpcStream::check_connection().is_err() {
-        if matches!(cli_command, Ok(CliCommand::Exit)) {
+        if matches!(msg, Message::CliCommand(CliCommand::Exit)) {
             // Background process already exited
             return Ok(());
         }
-        info!(target: "dispatcher", "Starting background process");
-        let dispatcher = DispatcherProc::spawn();
-        dispatcher.wait(2000)?;
+        dispatcher = Some(start_dispatcher(attach)?);
+    } else if attach {
+        info!(target: "dispatcher", "Background process already running - not attaching");
     }
 
-    let mut stream = IpcStream::connect("cli")?;
-    let msg: Message = exec_command
-        .map(Into::into)
-        .or_else(|_| cli_command.map(Into::into))?;
+    let mut stream = IpcStream::connect_with_retry("cli", CONNECT_RETRIES)?;
     stream.send_message(&msg)?;
     if matches!(msg, Message::CliCommand(CliCommand::Exit)) {
+        // Connection is closed, when all jobs are terminated
+        let _ = stream.receive_message();
         return Ok(());
     }
+    let code = receive_response(&mut stream, &msg, false)?;
+    if let Some(dispatcher) = dispatcher {
+        // Connections are handled sequentially, so the shutdown request needs a closed connection
+        drop(stream);
+        dispatcher.wait_attached();
+    }
+    if code != 0 {
+        process::exit(code);
+    }
+    Ok(())
+}
+
+/// Delay between attempts of `up --deadline`
+const UP_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Execute `up`, retrying after connection failures until the deadline.
+/// Services started in previous attempts are running and skipped by the background process.
+fn up_with_retry(cmd: &ExecCommand, deadline: Duration) -> Result<(), DispatcherError> {
+    let start = Instant::now();
+    loop {
+        let remaining = deadline.saturating_sub(start.elapsed());
+        let mut cmd = cmd.clone();
+        if let ExecCommand::Up { timeout, .. } = &mut cmd {
+            // Waiting for services is limited by the deadline as well
+            *timeout = (*timeout).min(remaining);
+        }
+        match request(&Message::ExecCommand(cmd)) {
+            Ok(code) => {
+                if code != 0 {
+                    process::exit(code);
+                }
+                return Ok(());
+            }
+            Err(
+                e @ (DispatcherError::IpcClientError(_) | DispatcherError::ProcSpawnTimeoutError),
+            ) if start.elapsed() + UP_RETRY_DELAY < deadline => {
+                error!(target: "dispatcher", "{e} - retrying services not started yet");
+                thread::sleep(UP_RETRY_DELAY);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Send request to background process, which is started if not running, and return exit code
+fn request(msg: &Message) -> Result<i32, DispatcherError> {
+    if IpcStream::check_connection().is_err() {
+        start_dispatcher(false)?;
+    }
+    let mut stream = IpcStream::connect_with_retry("cli", CONNECT_RETRIES)?;
+    stream.send_message(msg)?;
+    receive_response(&mut stream, msg, false)
+}
+
+fn start_dispatcher(attach: bool) -> Result<DispatcherProc, DispatcherError> {
+    info!(target: "dispatcher", "Starting background process");
+    let dispatcher = DispatcherProc::spawn(attach);
+    dispatcher.wait(2000)?;
+    Ok(dispatcher)
+}
+
+/// Request message for parsed command line, `None` if command is handled locally
+fn request_message(matches: &clap::ArgMatches) -> Result<Option<Message>, DispatcherError> {
+    let exec_command = ExecCommand::from_arg_matches(matches);
+    let cli_command = CliCommand::from_arg_matches(matches);
+
+    // Prevent recursive service starts from recipes calling shell-compose
+    let args = Cli::from_arg_matches(matches)?;
+    if let Ok(job_id) = env::var(ACTIVE_ENV_VAR) {
+        if !args.nested
+            && matches!(
+                exec_command,
+                Ok(ExecCommand::Start { .. } | ExecCommand::Up { .. })
+            )
+        {
+            return Err(DispatcherError::NestedInvocationError(job_id));
+        }
+    }
+
+    if let Ok(cmd @ ExecCommand::Run { dry_run: true, .. }) = &exec_command {
+        if let Some(job_info) = JobInfo::new_run_job(cmd) {
+            dry_run(job_info)?;
+            return Ok(None);
+        }
+    }
+
+    if let Ok(CliCommand::Doctor) = cli_command {
+        doctor();
+        return Ok(None);
+    }
+    if let Ok(CliCommand::Groups) = cli_command {
+        let justfile = Justfile::parse()?;
+        groups_table(&justfile.groups());
+        return Ok(None);
+    }
+    if let Ok(CliCommand::CronPreview { at, count, tz }) = &cli_command {
+        cron_preview(at, *count, *tz)?;
+        return Ok(None);
+    }
+
+    let msg = exec_command
+        .map(Into::into)
+        .or_else(|_| cli_command.map(Into::into))?;
+    Ok(Some(msg))
+}
+
+/// Execute commands read from stdin (one per line) using a single connection
+fn batch() -> Result<(), DispatcherError> {
+    if IpcStream::check_connection().is_err() {
+        start_dispatcher(false)?;
+    }
+    let mut stream = IpcStream::connect_with_retry("cli", CONNECT_RETRIES)?;
+    let mut failed = false;
+    for line in io::stdin().lines().map_while(Result::ok) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {
+            continue;
+        }
+        let msg = cli_parser()
+            .try_get_matches_from(["shell-compose"].iter().chain(&words))
+            .map_err(DispatcherError::from)
+            .and_then(|matches| request_message(&matches));
+        let msg = match msg {
+            Ok(Some(Message::CliCommand(CliCommand::Batch))) => {
+                error!(target: "dispatcher", "Nested batch command not supported");
+                failed = true;
+                continue;
+            }
+            Ok(Some(msg)) => msg,
+            Ok(None) => continue,
+            Err(e) => {
+                error!(target: "dispatcher", "{e}");
+                failed = true;
+                continue;
+            }
+        };
+        stream.send_message(&msg)?;
+        if matches!(msg, Message::CliCommand(CliCommand::Exit)) {
+            let _ = stream.receive_message();
+            break;
+        }
+        if receive_response(&mut stream, &msg, true)? != 0 {
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Receive and display response messages of a request and return its exit code
+///
+/// In a session, the connection is kept open for further requests and the
+/// final `Ok` message following listings is consumed.
+fn receive_response(
+    stream: &mut IpcStream,
+    msg: &Message,
+    session: bool,
+) -> Result<i32, DispatcherError> {
     let formatter = Formatter::default();
+    let (mut format, export, collapse) = match msg {
+        Message::CliCommand(CliCommand::Logs {
+            tz,
+            export,
+            collapse,
+            runs,
+            sanitize,
+            ..
+        }) => {
+            let format = LogLineFormat {
+                tz: *tz,
+                show_run: *runs,
+                sanitize: *sanitize,
+                prefix_width: 0,
+            };
+            (format, export.clone(), *collapse)
+        }
+        _ => (LogLineFormat::default(), None, false),
+    };
+    let mut exported_lines = Vec::new();
+    let mut repeated = RepeatedLine::default();
+    let mut responded = false;
     loop {
         let response = stream.receive_message();
+        if repeated.expired() || !matches!(response, Ok(Message::Connect | Message::LogLine(_))) {
+            repeated.flush(&formatter, &format);
+        }
         match response {
             Ok(Message::Connect) => {}
             Ok(Message::Ok) => {
+                if let Some(path) = export {
+                    export_logs(stream, &path, &exported_lines)?;
+                    return Ok(0);
+                }
                 match msg {
-                    Message::ExecCommand(_) | Message::CliCommand(CliCommand::Stop { .. }) => {
+                    Message::ExecCommand(_)
+                    | Message::CliCommand(
+                        CliCommand::Stop { .. }
+                        | CliCommand::Reload { .. }
+                        | CliCommand::Set { .. }
+                        | CliCommand::Pause { .. }
+                        | CliCommand::Resume { .. }
+                        | CliCommand::Signal { .. }
+                        | CliCommand::Hold { .. }
+                        | CliCommand::Unhold { .. },
+                    ) => {
                         info!(target: "dispatcher", "Command successful");
                     }
                     _ => {}
                 }
-                return Ok(());
+                return Ok(0);
             }
             Ok(Message::JobsStarted(job_ids)) => {
                 match job_ids.len() {
@@ -110,29 +360,352 @@ fn cli() -> Result<(), DispatcherError> {
                         info!(target: "dispatcher", "Jobs {} started", job_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", "))
                     }
                 }
-                return Ok(());
+                return Ok(0);
+            }
+            Ok(Message::JobsRenumbered(renumbered)) => {
+                if renumbered.is_empty() {
+                    info!(target: "dispatcher", "Job ids unchanged");
+                }
+                for (old_id, new_id) in renumbered {
+                    info!(target: "dispatcher", "Job {old_id} renumbered to {new_id}");
+                }
+            }
+            Ok(Message::CapturedOutput(output)) => {
+                print!("{output}");
+            }
+            Ok(Message::ExitCode(code)) => {
+                return Ok(code);
+            }
+            Ok(Message::Err(msg)) if msg == UNSUPPORTED_COMMAND => {
+                match daemon_version(stream) {
+                    Some(version) if version == CLIENT_VERSION => {
+                        error!(target: "dispatcher", "{msg}");
+                    }
+                    version => version_mismatch_error(version),
+                }
+                return Ok(1);
             }
             Ok(Message::Err(msg)) => {
                 error!(target: "dispatcher", "{msg} - Check logs for more information");
-                return Ok(());
+                return Ok(1);
+            }
+            Ok(Message::PsInfo(proc_infos))
+                if matches!(msg, Message::CliCommand(CliCommand::Watch { .. })) =>
+            {
+                formatter.log_info(&proc_status_line(&proc_infos));
             }
             Ok(Message::PsInfo(proc_infos)) => {
-                proc_info_table(&proc_infos);
-                return Ok(());
+                match msg {
+                    Message::CliCommand(CliCommand::Ps { json: true, .. }) => {
+                        let json = serde_json::to_string_pretty(&proc_infos).expect("serialize");
+                        println!("{json}");
+                    }
+                    Message::CliCommand(CliCommand::Ps { csv: true, .. }) => {
+                        proc_info_csv(&proc_infos);
+                    }
+                    Message::CliCommand(CliCommand::Ps { wide, .. }) => {
+                        proc_info_table(&proc_infos, *wide);
+                    }
+                    _ => proc_info_table(&proc_infos, false),
+                }
+                if !session {
+                    return Ok(0);
+                }
             }
             Ok(Message::JobInfo(job_infos)) => {
-                job_info_table(&job_infos);
-                return Ok(());
+                match msg {
+                    Message::CliCommand(CliCommand::Jobs { json: true, .. }) => {
+                        let json = serde_json::to_string_pretty(&job_infos).expect("serialize");
+                        println!("{json}");
+                    }
+                    Message::CliCommand(CliCommand::Inspect { .. }) => {
+                        for job in &job_infos {
+                            let json = serde_json::to_string_pretty(job).expect("serialize");
+                            println!("{json}");
+                        }
+                    }
+                    Message::CliCommand(CliCommand::Jobs {
+                        csv: true, format, ..
+                    }) => {
+                        let columns = if format.is_empty() {
+                            DEFAULT_JOB_COLUMNS
+                        } else {
+                            format
+                        };
+                        job_info_csv(&job_infos, columns);
+                    }
+                    Message::CliCommand(CliCommand::Jobs { format, .. }) if !format.is_empty() => {
+                        job_info_table(&job_infos, format);
+                    }
+                    _ => job_info_table(&job_infos, DEFAULT_JOB_COLUMNS),
+                }
+                if !session {
+                    return Ok(0);
+                }
+            }
+            Ok(Message::Environment(vars)) => {
+                for (key, value) in vars {
+                    println!("{key}={value}");
+                }
+                if !session {
+                    return Ok(0);
+                }
+            }
+            Ok(Message::ExitReason(reason)) => {
+                exit_reason_info(&reason);
+                if !session {
+                    return Ok(0);
+                }
+            }
+            Ok(Message::DaemonStatus(status)) => {
+                daemon_status_info(&status);
+                if !session {
+                    return Ok(0);
+                }
+            }
+            Ok(Message::Progress {
+                step,
+                total,
+                detail,
+            }) => {
+                info!(target: "dispatcher", "[{step}/{total}] {detail}");
+            }
+            Ok(Message::LogLine(log_line)) if export.is_some() => {
+                exported_lines.push(log_line);
+            }
+            Ok(Message::LogLine(log_line)) if collapse => {
+                format.align(&log_line);
+                if !repeated.add(&log_line) {
+                    repeated.flush(&formatter, &format);
+                    log_line.log(&formatter, &format);
+                    repeated.start(log_line);
+                }
             }
             Ok(Message::LogLine(log_line)) => {
-                log_line.log(&formatter);
+                format.align(&log_line);
+                log_line.log(&formatter, &format);
+            }
+            Err(e @ IpcClientError::ReadError(IpcStreamReadError::ReadError(_))) if !responded => {
+                // Older versions close the connection on unknown commands
+                let Ok(mut stream) = IpcStream::connect("cli") else {
+                    return Err(e.into());
+                };
+                match daemon_version(&mut stream) {
+                    Some(version) if version == CLIENT_VERSION => return Err(e.into()),
+                    version => version_mismatch_error(version),
+                }
+                return Ok(1);
             }
             Err(e) => return Err(e.into()),
             _ => return Err(DispatcherError::UnexpectedMessageError),
         }
+        responded = true;
     }
 }
 
-fn main() -> Result<(), DispatcherError> {
-    cli()
+/// Version of this client
+const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Report background process not supporting a command of this client
+fn version_mismatch_error(daemon_version: Option<String>) {
+    let version = daemon_version.unwrap_or("older".to_string());
+    error!(target: "dispatcher", "Command requires background process version {CLIENT_VERSION} (running: {version}) - restart it with `shell-compose exit`");
+}
+
+/// Maximal time a repeated log line is held back
+const REPEAT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Last log line with number of suppressed repetitions
+#[derive(Default)]
+struct RepeatedLine {
+    last: Option<LogLine>,
+    count: usize,
+    since: Option<Instant>,
+}
+
+impl RepeatedLine {
+    fn start(&mut self, log_line: LogLine) {
+        self.last = Some(log_line);
+        self.count = 0;
+        self.since = None;
+    }
+    /// Count repetition of last line. Returns false for a different line.
+    fn add(&mut self, log_line: &LogLine) -> bool {
+        let Some(last) = &mut self.last else {
+            return false;
+        };
+        if last.job_id != log_line.job_id || last.line != log_line.line {
+            return false;
+        }
+        last.ts = log_line.ts;
+        last.pid = log_line.pid;
+        self.count += 1;
+        self.since.get_or_insert_with(Instant::now);
+        true
+    }
+    fn expired(&self) -> bool {
+        self.since
+            .is_some_and(|since| since.elapsed() > REPEAT_FLUSH_INTERVAL)
+    }
+    /// Print repeated line with repetition count
+    fn flush(&mut self, formatter: &Formatter, format: &LogLineFormat) {
+        if let Some(last) = self.last.as_mut().filter(|_| self.count > 0) {
+            let mut entry = last.clone();
+            entry.line = format!("{} (x{})", last.line, self.count);
+            entry.log(formatter, format);
+        }
+        self.count = 0;
+        self.since = None;
+    }
+}
+
+/// Print normalized cron expression and its next execution times
+fn cron_preview(at: &str, count: usize, tz: Option<Tz>) -> Result<(), DispatcherError> {
+    let schedule = parse_schedule(at)?;
+    // Shows the seconds field added to 5-field expressions
+    println!("Schedule: {schedule}");
+    match tz {
+        Some(tz) => {
+            for ts in schedule.upcoming(tz).take(count) {
+                println!("{}", ts.format("%F %T %Z"));
+            }
+        }
+        None => {
+            for ts in schedule.upcoming(Local).take(count) {
+                println!("{}", ts.format("%F %T %Z"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Show socket path, connection state and resolved environment
+fn doctor() {
+    let socket = IpcStream::user_socket_name();
+    println!("Socket:                  {socket}");
+    println!("Socket file exists:      {}", Path::new(&socket).exists());
+    let status = IpcStream::connect("doctor").and_then(|mut stream| {
+        match stream.send_query(&CliCommand::Status.into())? {
+            Message::DaemonStatus(status) => Ok(Some(status)),
+            _ => Ok(None),
+        }
+    });
+    match &status {
+        Ok(_) => println!("Connection:              ok"),
+        Err(e) => println!("Connection:              {e}"),
+    }
+    if let Ok(Some(status)) = status {
+        println!("Daemon pid:              {}", status.pid);
+        println!("Daemon version:          {}", status.version);
+    }
+    println!("Client version:          {}", env!("CARGO_PKG_VERSION"));
+    let just = resolve_executable("just");
+    println!(
+        "just:                    {}",
+        just.as_deref()
+            .map(|path| path.display().to_string())
+            .unwrap_or("not found".to_string())
+    );
+    println!(
+        "TMPDIR:                  {}",
+        env::var("TMPDIR").unwrap_or("(not set)".to_string())
+    );
+}
+
+/// Query version of background process on an open connection
+fn daemon_version(stream: &mut IpcStream) -> Option<String> {
+    stream.send_message(&CliCommand::Status.into()).ok()?;
+    let mut version = None;
+    // Read until end of response, skipping status messages of an incompatible version
+    loop {
+        match stream.receive_message() {
+            Ok(Message::DaemonStatus(status)) => version = Some(status.version),
+            Err(IpcClientError::ReadError(IpcStreamReadError::DeserializeError(_))) => {}
+            _ => return version,
+        }
+    }
+}
+
+/// Show job info of `run` command without contacting the background process
+fn dry_run(job_info: JobInfo) -> Result<(), DispatcherError> {
+    let program = job_info
+        .args
+        .first()
+        .ok_or(DispatcherError::EmptyProcCommandError)?;
+    if resolve_executable(program).is_none() {
+        return Err(DispatcherError::ProcSpawnError(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("`{program}` not found"),
+        )));
+    }
+    let json = serde_json::to_string_pretty(&job_info.redacted()).expect("serialize");
+    println!("{json}");
+    Ok(())
+}
+
+/// Write log lines (one file per job) and job infos into a tar.gz archive
+fn export_logs(
+    stream: &mut IpcStream,
+    path: &Path,
+    log_lines: &[LogLine],
+) -> Result<(), DispatcherError> {
+    // Request job infos on the same connection
+    stream.send_message(
+        &CliCommand::Jobs {
+            json: false,
+            csv: false,
+            format: Vec::new(),
+        }
+        .into(),
+    )?;
+    let mut jobs = Vec::new();
+    loop {
+        match stream.receive_message()? {
+            Message::JobInfo(job_infos) => jobs = job_infos,
+            Message::Ok => break,
+            _ => return Err(DispatcherError::UnexpectedMessageError),
+        }
+    }
+
+    let mut logs: BTreeMap<JobId, String> = BTreeMap::new();
+    for entry in log_lines {
+        let dt = entry.ts.format("%F %T%.3f");
+        let stream = if entry.is_stderr { "stderr" } else { "stdout" };
+        writeln!(
+            logs.entry(entry.job_id).or_default(),
+            "{dt} [{}|{stream}] {}",
+            entry.pid,
+            entry.line
+        )
+        .expect("write");
+    }
+
+    let file = File::create(path).map_err(DispatcherError::LogExportError)?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let manifest = serde_json::to_string_pretty(&jobs).expect("serialize");
+    let mut append = |name: &str, content: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(Local::now().timestamp() as u64);
+        archive.append_data(&mut header, name, content)
+    };
+    append("manifest.json", manifest.as_bytes()).map_err(DispatcherError::LogExportError)?;
+    for (job_id, content) in &logs {
+        append(&format!("job-{job_id}.log"), content.as_bytes())
+            .map_err(DispatcherError::LogExportError)?;
+    }
+    archive
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(DispatcherError::LogExportError)?;
+    info!(target: "dispatcher", "Logs of {} jobs written to {}", logs.len(), path.display());
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = cli() {
+        error!(target: "dispatcher", "{e}");
+        process::exit(1);
+    }
 }
@@ -1,59 +1,124 @@
 use clap::{CommandFactory, FromArgMatches, Subcommand};
-use log::error;
+use log::{error, warn};
 use shell_compose::{
-    init_daemon_logger, start_ipc_listener, Cli, Dispatcher, ExecCommand, IpcStream, Message,
+    init_daemon_logger, remove_stale_socket, start_ipc_listener, CliCommand, DaemonCli, Dispatcher,
+    ExecCommand, IpcClientError, IpcStream, IpcStreamReadError, Message, UNSUPPORTED_COMMAND,
 };
-use std::fs::remove_file;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 fn run_server() {
-    let cli = Cli::command();
+    let cli = DaemonCli::command();
     let cli = ExecCommand::augment_subcommands(cli);
     let cli = cli.about(env!("CARGO_PKG_DESCRIPTION")); // Overwritten by augment_subcommands
     let matches = cli.clone().get_matches();
+    let args = DaemonCli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     let exec_command = ExecCommand::from_arg_matches(&matches);
 
     init_daemon_logger();
 
-    let mut dispatcher = Dispatcher::create();
+    let mut dispatcher = Dispatcher::create(&args);
+    let allowed_uids = args.allow_uids.clone();
 
     // Execute commands from CLI
     if let Ok(cmd) = exec_command {
-        dispatcher.exec_command(cmd);
+        dispatcher.exec_command(cmd, None);
     }
 
     let socket_name = IpcStream::user_socket_name();
     // reclaim_name in interprocess::local_socket::ListenerOptions
     // does not work, so we delete the socket first.
     if IpcStream::check_connection().is_err() {
-        remove_file(&socket_name).ok();
+        if let Err(e) = remove_stale_socket(&socket_name) {
+            error!("{e}");
+            std::process::exit(1);
+        }
     }
+    install_shutdown_handler();
+    let dispatcher = Arc::new(Mutex::new(dispatcher));
     start_ipc_listener(
         &socket_name,
-        move |mut stream| {
-            let Ok(_connect) = stream.receive_message() else {
-                return;
-            };
-
-            let Ok(request) = stream.receive_message() else {
-                return;
-            };
-            match request {
-                Message::Connect => {}
-                Message::ExecCommand(cmd) => {
-                    let response = dispatcher.exec_command(cmd);
-                    stream.send_message(&response).unwrap()
-                }
-                Message::CliCommand(cmd) => dispatcher.cli_command(cmd, &mut stream),
-                msg => {
-                    error!("Unexpected protocol message: `{msg:?}`");
-                }
-            }
+        move |stream| {
+            let dispatcher = dispatcher.clone();
+            let allowed_uids = allowed_uids.clone();
+            // Open connections (e.g. `batch` sessions) don't block other clients
+            thread::spawn(move || handle_connection(stream, &dispatcher, &allowed_uids));
         },
         Some(|e| panic!("Incoming connection error: {e}")),
     )
     .expect("Failed to start ipc listener");
 }
 
+/// Handle requests until the client closes the connection.
+/// The dispatcher is locked while executing a request only.
+fn handle_connection(mut stream: IpcStream, dispatcher: &Mutex<Dispatcher>, allowed_uids: &[u32]) {
+    if !allowed_uids.is_empty() && !stream.peer_allowed(allowed_uids) {
+        warn!("Rejected connection from user id {:?}", stream.peer_uid());
+        return;
+    }
+    let Ok(_connect) = stream.receive_message() else {
+        return;
+    };
+    loop {
+        let request = match stream.receive_message() {
+            Ok(request) => request,
+            Err(IpcClientError::ReadError(IpcStreamReadError::DeserializeError(e))) => {
+                // Message from a newer client version
+                error!("Unsupported protocol message: {e}");
+                let response = Message::Err(UNSUPPORTED_COMMAND.to_string());
+                let _ = stream.send_message(&response);
+                continue;
+            }
+            Err(_) => return,
+        };
+        match request {
+            Message::Connect => {}
+            Message::ExecCommand(cmd) => {
+                let response = dispatcher
+                    .lock()
+                    .expect("lock")
+                    .exec_command(cmd, Some(&mut stream));
+                let _ = stream.send_message(&response);
+            }
+            Message::CliCommand(cmd) => dispatcher
+                .lock()
+                .expect("lock")
+                .cli_command(cmd, &mut stream),
+            msg => {
+                error!("Unexpected protocol message: `{msg:?}`");
+                let response = Message::Err(UNSUPPORTED_COMMAND.to_string());
+                let _ = stream.send_message(&response);
+            }
+        }
+    }
+}
+
+/// Shut down cleanly on Ctrl-C or SIGTERM, by sending an exit request to the own socket.
+/// The request is handled after the currently executing request,
+/// so a repeated interrupt exits immediately.
+fn install_shutdown_handler() {
+    let interrupted = AtomicBool::new(false);
+    let res = ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+        warn!("Shutdown requested - terminating jobs");
+        match IpcStream::connect("shutdown") {
+            Ok(mut stream) => {
+                let _ = stream.send_message(&Message::CliCommand(CliCommand::Exit));
+                // Background process exits after terminating all jobs
+                let _ = stream.receive_message();
+            }
+            Err(_) => process::exit(130),
+        }
+    });
+    if let Err(e) = res {
+        error!("Failed to install signal handler: {e}");
+    }
+}
+
 fn main() {
     run_server();
 }
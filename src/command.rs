@@ -1,65 +1,628 @@
-use crate::{DispatcherError, Job, JobId, LogLine, ProcInfo};
-use clap::{Parser, Subcommand};
+use crate::{
+    BackoffConfig, DaemonStatus, DispatcherError, ExitReason, Job, JobId, LogLine, ProcInfo,
+    Restart, RestartInfo,
+};
+use bytesize::ByteSize;
+use chrono::{DateTime, Local, NaiveDateTime, TimeDelta};
+use chrono_tz::Tz;
+use clap::builder::BoolishValueParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
-pub struct Cli;
+pub struct Cli {
+    /// Allow starting services from within a job managed by shell-compose
+    #[arg(long, global = true)]
+    pub nested: bool,
+    /// Keep a newly started background process attached to the terminal until it exits (Ctrl-C)
+    #[arg(long, global = true)]
+    pub attach_daemon: bool,
+}
+
+/// Background service options
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct DaemonCli {
+    /// Memory limit for buffered log lines of all jobs (e.g. `100MB`)
+    #[arg(long, env = "SHELL_COMPOSE_LOG_MEMORY")]
+    pub log_memory: Option<ByteSize>,
+    /// Maximal number of buffered log lines per process (or `unbounded`)
+    #[arg(long, value_name = "LINES", env = "SHELL_COMPOSE_LOG_LINES", default_value = "200", value_parser = parse_log_lines)]
+    pub log_lines: LogBufferSize,
+    /// Only allow running these executables (comma separated names or absolute paths)
+    #[arg(long, value_delimiter = ',', env = "SHELL_COMPOSE_ALLOW_COMMANDS")]
+    pub allow_commands: Vec<String>,
+    /// Run every job once, overriding restart policies of services
+    #[arg(long, env = "SHELL_COMPOSE_NO_RESTART", value_parser = BoolishValueParser::new())]
+    pub no_restart: bool,
+    /// Start jobs with only PATH and HOME from the daemon environment, plus job variables
+    #[arg(long, env = "SHELL_COMPOSE_CLEAN_ENV", value_parser = BoolishValueParser::new())]
+    pub clean_env: bool,
+    /// Record environment of spawned processes for `inspect --env` (includes secret values)
+    #[arg(long, env = "SHELL_COMPOSE_RECORD_ENV", value_parser = BoolishValueParser::new())]
+    pub record_env: bool,
+    /// Append process lifecycle events as JSON lines to this file
+    #[arg(long, value_name = "FILE", env = "SHELL_COMPOSE_AUDIT_FILE")]
+    pub audit_file: Option<PathBuf>,
+    /// Only accept connections from the current user and these user ids (comma separated)
+    #[arg(long, value_delimiter = ',', env = "SHELL_COMPOSE_ALLOW_UIDS")]
+    pub allow_uids: Vec<u32>,
+    /// Serve process metrics for Prometheus at `http://<ADDR>/metrics` (e.g. `127.0.0.1:9100`)
+    #[cfg(feature = "metrics")]
+    #[arg(long, value_name = "ADDR", env = "SHELL_COMPOSE_METRICS_ADDR")]
+    pub metrics_addr: Option<std::net::SocketAddr>,
+}
 
 /// Shared commands with background service
-#[derive(Subcommand, Debug, Serialize, Deserialize)]
+#[derive(Subcommand, Clone, Debug, Serialize, Deserialize)]
 pub enum ExecCommand {
     /// Execute command
     Run {
+        /// Wait for termination and exit with the exit code of the command
+        #[arg(long)]
+        wait: bool,
+        /// Suppress command output while waiting
+        #[arg(long, requires = "wait")]
+        quiet: bool,
+        /// Validate command and show job info without executing it
+        #[arg(long, conflicts_with = "wait")]
+        dry_run: bool,
+        /// Wait for termination, print complete stdout output and exit with the exit code of the command
+        #[arg(long, conflicts_with_all = ["wait", "dry_run", "log_streams"])]
+        capture: bool,
+        /// Output streams which are buffered for logs
+        #[arg(long, value_name = "STREAMS", default_value = "both")]
+        log_streams: LogStreams,
+        /// Request line buffered output, so that log lines are available immediately
+        #[arg(long)]
+        no_buffer: bool,
+        /// Append output lines to this file
+        #[arg(long, value_name = "FILE", value_parser = parse_path)]
+        log_file: Option<PathBuf>,
+        /// Execute command with the system shell (`sh -c` or `cmd /C`), e.g. for pipes and globs
+        #[arg(long)]
+        shell: bool,
+        /// Run with the environment and working directory of the services of a justfile group
+        #[arg(long, value_name = "GROUP", conflicts_with = "dry_run")]
+        group_env: Option<String>,
+        /// Restart policy of the command
+        #[arg(long, value_name = "POLICY", conflicts_with_all = ["wait", "capture"])]
+        restart: Option<Restart>,
+        /// Waiting time before restart in ms
+        #[arg(long, value_name = "MS", requires = "restart")]
+        restart_wait: Option<u64>,
+        #[command(flatten)]
+        backoff: BackoffOptions,
+        /// Environment variable
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Environment variable with a value which is not displayed
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_secret_env_var)]
+        secret_env: Vec<(String, Secret)>,
+        /// Working directory of the command
+        #[arg(long, value_name = "DIR", value_parser = parse_dir)]
+        cwd: Option<PathBuf>,
         /// Command arguments
+        #[arg(required = true, value_name = "COMMAND")]
         args: Vec<String>,
     },
     /// Execute command with cron schedule
     Runat {
-        /// Cron expression
+        /// Cron expression (with or without seconds field)
         at: String,
         /// Command arguments
+        #[arg(required = true, value_name = "COMMAND")]
         args: Vec<String>,
     },
+    /// Execute cron job immediately, keeping its schedule
+    Trigger {
+        /// Job id
+        job_id: JobId,
+    },
+    /// Restart running service with cron schedule
+    RestartAt {
+        /// Cron expression (with or without seconds field)
+        at: String,
+        /// Service name
+        #[arg(value_parser = parse_name)]
+        service: String,
+    },
     /// Start service
     Start {
         /// Service name
+        #[arg(value_parser = parse_name)]
         service: String,
+        #[command(flatten)]
+        options: ServiceOptions,
     },
     /// Start service group
     Up {
         /// Service group name
+        #[arg(value_parser = parse_name)]
         group: String,
+        #[command(flatten)]
+        selection: ServiceSelection,
+        #[command(flatten)]
+        pacing: StartupPacing,
+        /// Wait until all services are running
+        #[arg(long)]
+        wait: bool,
+        /// Maximal waiting time for services to be running
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "60s", requires = "wait")]
+        timeout: Duration,
+        /// Overall time limit for retrying services not started yet after connection failures
+        #[arg(long, value_parser = humantime::parse_duration)]
+        deadline: Option<Duration>,
+        #[command(flatten)]
+        options: ServiceOptions,
     },
 }
 
+/// Selection of services in a group
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+pub struct ServiceSelection {
+    /// Only these services of the group (comma separated)
+    #[arg(long, value_delimiter = ',', conflicts_with = "except")]
+    pub only: Vec<String>,
+    /// All services of the group except these (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    pub except: Vec<String>,
+}
+
+/// Pacing of service starts in a group
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+pub struct StartupPacing {
+    /// Maximal number of services starting at the same time
+    #[arg(long)]
+    pub concurrency: Option<NonZeroUsize>,
+    /// Random delay between service starts (e.g. `0-2s` or `500ms`)
+    #[arg(long, value_name = "MIN-MAX", value_parser = parse_stagger)]
+    pub stagger: Option<Stagger>,
+}
+
+/// Range of random delays
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Stagger {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Stagger {
+    /// Random delay within range
+    pub fn delay(&self) -> Duration {
+        let range = (self.max - self.min).as_millis() as u64;
+        self.min + Duration::from_millis(fastrand::u64(0..=range))
+    }
+}
+
+/// Parse delay range like `0-2s`, `1s-3s` or `2s` (same as `0-2s`).
+/// A lower bound without unit uses the unit of the upper bound.
+fn parse_stagger(s: &str) -> Result<Stagger, String> {
+    let (min, max) = s.split_once('-').unwrap_or(("0", s));
+    let max_duration = humantime::parse_duration(max).map_err(|e| e.to_string())?;
+    let min_duration = if min.chars().all(|c| c.is_ascii_digit()) {
+        let unit = max.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+        humantime::parse_duration(&format!("{min}{unit}"))
+    } else {
+        humantime::parse_duration(min)
+    }
+    .map_err(|e| e.to_string())?;
+    if min_duration > max_duration {
+        return Err(format!("lower bound of `{s}` exceeds upper bound"));
+    }
+    Ok(Stagger {
+        min: min_duration,
+        max: max_duration,
+    })
+}
+
+/// Parse path relative to the current directory of the client
+fn parse_path(s: &str) -> Result<PathBuf, String> {
+    std::path::absolute(s).map_err(|e| e.to_string())
+}
+
+/// Parse path of an existing directory relative to the current directory of the client
+fn parse_dir(s: &str) -> Result<PathBuf, String> {
+    let path = parse_path(s)?;
+    if !path.is_dir() {
+        return Err(format!("`{}` is not a directory", path.display()));
+    }
+    Ok(path)
+}
+
+/// Size of per-process log buffers
+#[derive(Clone, Copy, Debug)]
+pub struct LogBufferSize(pub Option<usize>);
+
+/// Parse number of lines or `unbounded`
+fn parse_log_lines(s: &str) -> Result<LogBufferSize, String> {
+    if s == "unbounded" {
+        return Ok(LogBufferSize(None));
+    }
+    let lines = s.parse::<usize>().map_err(|e| e.to_string())?;
+    Ok(LogBufferSize(Some(lines)))
+}
+
+/// Parse service or group name, which is passed to `just` as argument.
+/// Recipes of modules are separated by `::` (e.g. `db::migrate`).
+/// Surrounding whitespace is removed.
+fn parse_name(s: &str) -> Result<String, String> {
+    let name = s.trim();
+    if name.is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    if name.starts_with('-') {
+        return Err(format!("`{name}` must not start with `-`"));
+    }
+    if name.split("::").any(str::is_empty) {
+        return Err(format!("`{name}` contains an empty module path segment"));
+    }
+    if let Some(c) = name
+        .replace("::", "")
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        return Err(format!(
+            "invalid character `{c}` in `{name}` (allowed: alphanumeric, `-`, `_`, `::`)"
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Options for starting services
+#[derive(Args, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct ServiceOptions {
+    /// Constant delay between restarts (e.g. `5s`)
+    #[arg(long, value_parser = humantime::parse_duration, conflicts_with_all = ["backoff_multiplier", "backoff_max"])]
+    pub restart_delay: Option<Duration>,
+    /// Restart service only after successful termination (e.g. polling loops)
+    #[arg(long, conflicts_with = "restart")]
+    pub restart_on_success: bool,
+    /// Restart policy overriding the default of the service
+    #[arg(long, value_name = "POLICY")]
+    pub restart: Option<Restart>,
+    /// Command prefix for starting the service (e.g. `'time -v'`)
+    #[arg(long, value_name = "COMMAND", allow_hyphen_values = true)]
+    pub wrap: Option<String>,
+    /// Output streams which are buffered for logs
+    #[arg(long, value_name = "STREAMS")]
+    pub log_streams: Option<LogStreams>,
+    /// Request line buffered output, so that log lines are available immediately
+    #[arg(long)]
+    pub no_buffer: bool,
+    #[command(flatten)]
+    pub backoff: BackoffOptions,
+    /// Command checking readiness of the service, retried by `up --wait` until it succeeds
+    #[arg(long, value_name = "COMMAND", allow_hyphen_values = true)]
+    pub ready_cmd: Option<String>,
+    /// Command executed before stopping the service (e.g. `'curl -X POST localhost/drain'`)
+    #[arg(long, value_name = "COMMAND", allow_hyphen_values = true)]
+    pub pre_stop: Option<String>,
+}
+
+impl ServiceOptions {
+    /// Override restart policy and delays of a service with given options
+    pub fn apply_restart(&self, restart: &mut RestartInfo) {
+        self.backoff.apply(&mut restart.backoff);
+        if let Some(delay) = self.restart_delay {
+            restart.wait_time = delay.as_millis() as u64;
+            // Not increased by backoff
+            restart.backoff.multiplier = 1.0;
+        }
+        if self.restart_on_success {
+            restart.policy = Restart::OnSuccess;
+        }
+        if let Some(policy) = &self.restart {
+            restart.policy = policy.clone();
+        }
+    }
+}
+
+/// Growth of delays between restarts
+#[derive(Args, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct BackoffOptions {
+    /// Factor applied to the restart delay for each further restart [default: 2]
+    #[arg(long, value_name = "FACTOR", value_parser = parse_multiplier)]
+    pub backoff_multiplier: Option<f64>,
+    /// Upper limit of the restart delay [default: 30s]
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub backoff_max: Option<Duration>,
+    /// Window for counting restarts, which increase the restart delay [default: 1m]
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub backoff_reset: Option<Duration>,
+}
+
+impl BackoffOptions {
+    /// Override backoff parameters with given options
+    pub fn apply(&self, backoff: &mut BackoffConfig) {
+        if let Some(multiplier) = self.backoff_multiplier {
+            backoff.multiplier = multiplier;
+        }
+        if let Some(max) = self.backoff_max {
+            backoff.max = max;
+        }
+        if let Some(reset_after) = self.backoff_reset {
+            backoff.reset_after = reset_after;
+        }
+    }
+}
+
+fn parse_multiplier(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor >= 1.0 => Ok(factor),
+        Ok(_) => Err(format!("factor `{s}` must be at least 1")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Captured output streams of a process
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub enum LogStreams {
+    #[default]
+    Both,
+    Stdout,
+    Stderr,
+    /// Output is discarded
+    None,
+}
+
+impl LogStreams {
+    pub fn stdout(self) -> bool {
+        matches!(self, LogStreams::Both | LogStreams::Stdout)
+    }
+    pub fn stderr(self) -> bool {
+        matches!(self, LogStreams::Both | LogStreams::Stderr)
+    }
+}
+
+/// Value which is redacted in debug output and in job listings
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Secret(pub String);
+
+impl Secret {
+    pub fn redacted() -> Self {
+        Secret("***".to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or(format!("invalid KEY=VALUE: no `=` found in `{s}`"))
+}
+
+fn parse_secret_env_var(s: &str) -> Result<(String, Secret), String> {
+    parse_env_var(s).map(|(key, value)| (key, Secret(value)))
+}
+
 /// Additional commands
 #[derive(Subcommand, Debug, Serialize, Deserialize)]
 pub enum CliCommand {
     /// Stop service group
     Down {
         /// Service group name
+        #[arg(value_parser = parse_name)]
         group: String,
+        #[command(flatten)]
+        selection: ServiceSelection,
     },
     /// Stop job
     Stop {
         /// Job id
         job_id: JobId,
+        /// Terminate all descendant processes, including processes escaping the parent
+        #[arg(long)]
+        tree: bool,
+        /// Grace period between SIGTERM and SIGKILL
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
+        timeout: Duration,
+    },
+    /// Restart running job, keeping its job id
+    Restart {
+        /// Job id or service name
+        job_or_service: String,
+    },
+    /// Send signal to running processes of job (e.g. `HUP` for reloading configuration)
+    Signal {
+        /// Job id or service name
+        job_or_service: String,
+        /// Signal name (`HUP` or `SIGHUP`) or number
+        signal: String,
+    },
+    /// Restart service if its recipe changed
+    Reload {
+        /// Service name
+        #[arg(value_parser = parse_name)]
+        service: String,
+    },
+    /// Change settings of job processes
+    Set {
+        /// Job id
+        job_id: JobId,
+        /// Maximal number of buffered log lines
+        #[arg(long, value_name = "LINES")]
+        log_buffer: Option<usize>,
+    },
+    /// Suspend running processes of job
+    Pause {
+        /// Job id
+        job_id: JobId,
+    },
+    /// Continue paused processes of job
+    Resume {
+        /// Job id
+        job_id: JobId,
+    },
+    /// Suppress restarts of job, keeping its processes running
+    Hold {
+        /// Job id
+        job_id: JobId,
+    },
+    /// Enable restarts of held job again
+    Unhold {
+        /// Job id
+        job_id: JobId,
     },
     /// List processes
-    Ps,
+    Ps {
+        /// Show all columns regardless of terminal width
+        #[arg(long, conflicts_with = "csv")]
+        wide: bool,
+        /// Output process infos as CSV
+        #[arg(long)]
+        csv: bool,
+        /// Output process infos as JSON, with raw numbers and RFC 3339 timestamps
+        #[arg(long, conflicts_with_all = ["wide", "csv"])]
+        json: bool,
+        /// Skip CPU sampling for an immediate response (CPU usage is shown as 0)
+        #[arg(long, conflicts_with = "cpu_interval")]
+        no_cpu: bool,
+        /// Duration of CPU sampling (default 200ms)
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+        cpu_interval: Option<Duration>,
+    },
     /// List active jobs
-    Jobs,
+    Jobs {
+        /// Output job infos as JSON
+        #[arg(long, conflicts_with_all = ["format", "csv"])]
+        json: bool,
+        /// Output job infos as CSV
+        #[arg(long)]
+        csv: bool,
+        /// Columns to display (comma separated)
+        #[arg(long, value_delimiter = ',')]
+        format: Vec<JobColumn>,
+    },
+    /// Show job details
+    Inspect {
+        /// Job id
+        job_id: JobId,
+        /// Show environment of the latest job process
+        #[arg(long)]
+        env: bool,
+    },
+    /// Show why the latest process of a job terminated
+    Why {
+        /// Job id
+        job_id: JobId,
+    },
+    /// Assign sequential ids to active jobs
+    Renumber,
+    /// Show status of background process
+    Status,
+    /// List service groups defined in justfile
+    Groups,
+    /// Show upcoming execution times of a cron expression without scheduling a job
+    CronPreview {
+        /// Cron expression (with or without seconds field)
+        at: String,
+        /// Number of execution times
+        #[arg(short = 'n', long, default_value_t = 5)]
+        count: usize,
+        /// Show times in this timezone (e.g. `America/Chicago`)
+        #[arg(long, value_name = "TIMEZONE")]
+        tz: Option<Tz>,
+    },
     /// Show process logs
     Logs {
         /// Job id or service name
         job_or_service: Option<String>,
-        // --tail: Option<usize>,
+        /// Show logs of all services in group
+        #[arg(long, conflicts_with = "job_or_service")]
+        group: Option<String>,
+        /// Show timestamps in this timezone (e.g. `America/Chicago`)
+        #[arg(long, value_name = "TIMEZONE")]
+        tz: Option<Tz>,
+        /// Write buffered logs and job infos into a tar.gz archive
+        #[arg(long, value_name = "FILE")]
+        export: Option<PathBuf>,
+        /// Collapse consecutive identical lines into `<line> (xN)`
+        #[arg(long, conflicts_with = "export")]
+        collapse: bool,
+        /// Show run number of restarted processes (e.g. `[1#3|1234]`)
+        #[arg(long)]
+        runs: bool,
+        /// Show control characters of log lines as escape sequences (e.g. `\r`)
+        #[arg(long)]
+        sanitize: bool,
+        /// Number of buffered lines per process shown before following new output
+        #[arg(long, value_name = "N", conflicts_with = "export")]
+        tail: Option<usize>,
+        /// Show buffered lines newer than this time (e.g. `2024-06-01 12:00:00` or `5m`)
+        #[arg(long, value_name = "TIME", conflicts_with = "tail")]
+        since: Option<String>,
+        /// Show stdout lines only
+        #[arg(long, conflicts_with = "stderr")]
+        stdout: bool,
+        /// Show stderr lines only
+        #[arg(long)]
+        stderr: bool,
     },
+    /// Show socket path and connection diagnostics
+    Doctor,
+    /// Follow logs of service group with periodic status lines
+    Watch {
+        /// Service group name
+        #[arg(value_parser = parse_name)]
+        group: String,
+        /// Time between status lines
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+        interval: Duration,
+    },
+    /// Execute commands read from stdin (one per line, without shell quoting)
+    Batch,
     /// Stop all processes
     Exit,
 }
 
+/// Parse absolute local time or duration before now (e.g. `5m`)
+pub fn parse_since(s: &str) -> Result<DateTime<Local>, DispatcherError> {
+    if let Ok(duration) = humantime::parse_duration(s) {
+        if let Some(ts) = TimeDelta::from_std(duration)
+            .ok()
+            .and_then(|delta| Local::now().checked_sub_signed(delta))
+        {
+            return Ok(ts);
+        }
+    }
+    if let Ok(ts) = DateTime::parse_from_rfc3339(s) {
+        return Ok(ts.with_timezone(&Local));
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .and_then(|ts| ts.and_local_timezone(Local).earliest())
+        .ok_or(DispatcherError::InvalidTimeError(s.to_string()))
+}
+
+/// Column of job listing
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum JobColumn {
+    Id,
+    Type,
+    Command,
+    At,
+    /// Next execution of scheduled jobs
+    Next,
+    Restart,
+    Output,
+}
+
+/// Error response of background process to messages it does not know
+pub const UNSUPPORTED_COMMAND: &str =
+    "Command not supported by background process (restart it with `shell-compose exit`)";
+
 /// IPC messages
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Message {
@@ -71,9 +634,21 @@ pub enum Message {
     // cli <- Listener
     PsInfo(Vec<ProcInfo>),
     JobInfo(Vec<Job>),
+    DaemonStatus(DaemonStatus),
+    Environment(Vec<(String, String)>),
+    ExitReason(ExitReason),
     LogLine(LogLine),
+    Progress {
+        step: usize,
+        total: usize,
+        detail: String,
+    },
     Ok,
     JobsStarted(Vec<JobId>),
+    /// Changed job ids (old, new)
+    JobsRenumbered(Vec<(JobId, JobId)>),
+    CapturedOutput(String),
+    ExitCode(i32),
     Err(String),
 }
 
@@ -99,3 +674,149 @@ impl From<Result<(), DispatcherError>> for Message {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn stagger_ranges() {
+        let stagger = parse_stagger("0-2s").unwrap();
+        assert_eq!(
+            (stagger.min, stagger.max),
+            (Duration::ZERO, Duration::from_secs(2))
+        );
+        let stagger = parse_stagger("1s-3s").unwrap();
+        assert_eq!(
+            (stagger.min, stagger.max),
+            (Duration::from_secs(1), Duration::from_secs(3))
+        );
+        // Lower bound without unit
+        let stagger = parse_stagger("500-1500ms").unwrap();
+        assert_eq!(
+            (stagger.min, stagger.max),
+            (Duration::from_millis(500), Duration::from_millis(1500))
+        );
+        // Upper bound only
+        let stagger = parse_stagger("2s").unwrap();
+        assert_eq!(
+            (stagger.min, stagger.max),
+            (Duration::ZERO, Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn invalid_stagger() {
+        assert!(parse_stagger("3s-1s").is_err());
+        assert!(parse_stagger("").is_err());
+        assert!(parse_stagger("1s-").is_err());
+        assert!(parse_stagger("fast").is_err());
+    }
+
+    #[test]
+    fn stagger_delay_within_range() {
+        let stagger = parse_stagger("10ms-20ms").unwrap();
+        for _ in 0..100 {
+            let delay = stagger.delay();
+            assert!(delay >= stagger.min && delay <= stagger.max);
+        }
+        assert_eq!(parse_stagger("0s").unwrap().delay(), Duration::ZERO);
+    }
+
+    #[test]
+    fn valid_names() {
+        assert_eq!(parse_name("web").unwrap(), "web");
+        assert_eq!(parse_name("  db-main_2 ").unwrap(), "db-main_2");
+        assert_eq!(parse_name("db::migrate").unwrap(), "db::migrate");
+        // Dash within name
+        assert_eq!(parse_name("a-").unwrap(), "a-");
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert!(parse_name("").is_err());
+        assert!(parse_name("   ").is_err());
+        // Would be passed to `just` as option
+        assert!(parse_name("-h").is_err());
+        assert!(parse_name(" --dump").is_err());
+        assert!(parse_name("db::").is_err());
+        assert!(parse_name("::db").is_err());
+        assert!(parse_name("db:::migrate").is_err());
+        assert!(parse_name("db migrate").is_err());
+        assert!(parse_name("db;rm").is_err());
+        assert!(parse_name("db:migrate").is_err());
+    }
+
+    #[test]
+    fn since_duration() {
+        let before = Local::now();
+        let ts = parse_since("10m").unwrap();
+        let after = Local::now();
+        assert!(ts >= before - TimeDelta::minutes(10));
+        assert!(ts <= after - TimeDelta::minutes(10));
+        assert!(parse_since("1h 30m").unwrap() < parse_since("1h").unwrap());
+    }
+
+    #[test]
+    fn since_timestamp() {
+        let ts = parse_since("2024-03-01T12:30:00+02:00").unwrap();
+        assert_eq!(
+            ts,
+            DateTime::parse_from_rfc3339("2024-03-01T10:30:00Z").unwrap()
+        );
+        let local = Local.with_ymd_and_hms(2024, 3, 1, 12, 30, 15).unwrap();
+        assert_eq!(parse_since("2024-03-01 12:30:15").unwrap(), local);
+        assert_eq!(parse_since("2024-03-01T12:30:15").unwrap(), local);
+        assert_eq!(
+            parse_since("2024-03-01 12:30").unwrap(),
+            local - TimeDelta::seconds(15)
+        );
+    }
+
+    #[test]
+    fn invalid_since() {
+        assert!(matches!(
+            parse_since("yesterday"),
+            Err(DispatcherError::InvalidTimeError(_))
+        ));
+        assert!(parse_since("").is_err());
+        assert!(parse_since("2024-13-01 12:00").is_err());
+        assert!(parse_since("-5m").is_err());
+        // Out of range of date types
+        assert!(parse_since("100000000000y").is_err());
+    }
+
+    #[test]
+    fn constant_restart_delay() {
+        let options = ServiceOptions {
+            restart_delay: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let mut restart = RestartInfo::default();
+        options.apply_restart(&mut restart);
+        for restarts in [0, 1, 2, 10, usize::MAX] {
+            assert_eq!(restart.delay(restarts), Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn restart_delay_conflicts_with_backoff() {
+        let parse = |args: &[&str]| {
+            let cli = ExecCommand::augment_subcommands(clap::Command::new("shell-compose"));
+            cli.try_get_matches_from(["shell-compose", "start", "web"].iter().chain(args))
+        };
+        assert!(parse(&["--restart-delay", "5s"]).is_ok());
+        assert!(parse(&["--backoff-max", "1m"]).is_ok());
+        assert!(parse(&["--restart-delay", "5s", "--backoff-multiplier", "3"]).is_err());
+        assert!(parse(&["--restart-delay", "5s", "--backoff-max", "1m"]).is_err());
+    }
+
+    #[test]
+    fn workdir_must_exist() {
+        let dir = std::env::temp_dir();
+        assert_eq!(parse_dir(dir.to_str().unwrap()).unwrap(), dir);
+        assert!(parse_dir("Cargo.toml").is_err());
+        assert!(parse_dir("nonexistent-workdir").is_err());
+    }
+}
@@ -1,38 +1,79 @@
 use crate::{
-    CliCommand, ExecCommand, IpcClientError, IpcStream, Justfile, JustfileError, Message,
-    ProcStatus, Runner,
+    descendant_processes, interpolate_env, main_process, parse_signal, parse_since, AuditEvent,
+    AuditLog, CliCommand, DaemonCli, ExecCommand, IpcClientError, IpcStream, Justfile,
+    JustfileError, LogBudget, LogLine, LogStreams, Message, OutputOptions, OutputStats, ProcInfo,
+    ProcStatus, Readiness, Runner, RunnerContext, Secret, ServiceOptions, ServiceSelection,
+    StartupPacing, Termination, DEFAULT_GRACE_PERIOD,
 };
-use chrono::{DateTime, Local, TimeZone};
+use chrono::{DateTime, Local, TimeDelta, TimeZone};
+use clap::ValueEnum;
 use job_scheduler_ng::{self as job_scheduler, JobScheduler};
-use log::{error, info};
+use log::{error, info, warn};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
 use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, RefreshKind, System};
 use thiserror::Error;
 
 pub type JobId = u32;
 pub type Pid = u32;
 
+/// Scheduled job registered in the cron scheduler
+struct CronEntry {
+    uuid: job_scheduler::Uuid,
+    /// Checked by the scheduled closure before spawning
+    stopping: Arc<AtomicBool>,
+}
+
 pub struct Dispatcher<'a> {
     jobs: BTreeMap<JobId, JobInfo>,
     last_job_id: JobId,
-    cronjobs: HashMap<JobId, job_scheduler::Uuid>,
+    cronjobs: HashMap<JobId, CronEntry>,
     procs: Arc<Mutex<Vec<Runner>>>,
     scheduler: Arc<Mutex<JobScheduler<'a>>>,
     system: System,
-    /// Sender channel for Runner threads
-    channel: mpsc::Sender<Pid>,
+    /// Shared context for Runner threads
+    runner_ctx: RunnerContext,
+    started: DateTime<Local>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct JobInfo {
     pub job_type: JobType,
     pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// Environment variables with values hidden in job listings
+    pub secret_env: Vec<(String, Secret)>,
     pub entrypoint: Option<String>,
+    /// Command prefix prepended to args when spawning (e.g. `time -v`)
+    pub wrap: Vec<String>,
+    /// Command checking readiness of a service after startup (e.g. `pg_isready`)
+    pub ready_cmd: Vec<String>,
+    /// Readiness probe awaited when starting a service
+    pub readiness: Option<Readiness>,
+    /// Command executed before stopping a service (e.g. deregistering from a load balancer)
+    pub pre_stop: Vec<String>,
+    /// Working directory of spawned processes
+    pub workdir: Option<PathBuf>,
+    /// Output streams buffered for logs
+    pub log_streams: LogStreams,
+    /// File where output lines are appended
+    #[serde(default)]
+    pub log_file: Option<PathBuf>,
+    /// Request line buffered output from processes
+    pub no_buffer: bool,
+    /// Recipe definition of a service when started
+    #[serde(skip)]
+    pub recipe: Option<String>,
     pub restart: RestartInfo,
     // stats: #Runs, #Success, #Restarts
 }
@@ -42,26 +83,76 @@ pub enum JobType {
     Shell,
     Service(String),
     Cron(String),
+    /// Scheduled restart of a service (cron expression, service name)
+    CronRestart(String, String),
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct RestartInfo {
     pub policy: Restart,
-    /// Waiting time before restart in ms
+    /// Initial waiting time before restart in ms
     pub wait_time: u64,
+    /// Maximal number of restarts within the backoff window
+    pub max_restarts: usize,
+    pub backoff: BackoffConfig,
 }
 
-/// Restart policy
+/// Growth of waiting times between restarts
 #[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BackoffConfig {
+    /// Factor applied to the waiting time for each further restart within the window
+    pub multiplier: f64,
+    /// Upper limit of the waiting time
+    pub max: Duration,
+    /// Window for counting restarts. Older restarts don't increase the waiting time.
+    pub reset_after: Duration,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        BackoffConfig {
+            multiplier: 2.0,
+            max: Duration::from_secs(30),
+            reset_after: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Restart policy
+#[derive(ValueEnum, Clone, Serialize, Deserialize, Debug)]
 pub enum Restart {
     Always,
     OnFailure,
+    /// Restart after successful termination (polling loops)
+    OnSuccess,
     Never,
 }
 
-struct JobSpawnInfo<'a> {
+/// Output handling while waiting for process termination
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum WaitMode {
+    /// Stream log lines unless quiet
+    Output { quiet: bool },
+    /// Send complete stdout output after termination
+    Capture,
+}
+
+/// Log lines selected for output
+struct LogSelection {
+    /// Job id or service name
+    job_or_service: Option<String>,
+    /// Service group
+    group: Option<String>,
+    streams: LogStreams,
+}
+
+struct JobSpawnInfo {
     job_id: JobId,
-    args: &'a [String],
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    workdir: Option<PathBuf>,
+    log_streams: LogStreams,
+    log_file: Option<PathBuf>,
     restart_info: RestartInfo,
 }
 
@@ -69,6 +160,36 @@ struct JobSpawnInfo<'a> {
 pub struct Job {
     pub id: JobId,
     pub info: JobInfo,
+    /// Captured output of all job processes
+    pub output: OutputStats,
+    /// Next execution of a scheduled job
+    pub next_run: Option<DateTime<Local>>,
+}
+
+/// Background process status
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DaemonStatus {
+    /// Process id of background process
+    pub pid: u32,
+    /// Version of background process
+    pub version: String,
+    /// Start time of background process
+    pub started: DateTime<Local>,
+    /// Number of active jobs
+    pub jobs: usize,
+    /// Number of running processes
+    pub running: usize,
+    /// Process terminations not yet handled by the child watcher
+    pub pending_exits: usize,
+}
+
+/// Last termination of a job process
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ExitReason {
+    pub job_id: JobId,
+    /// Final state of the process
+    pub state: ProcStatus,
+    pub end: DateTime<Local>,
 }
 
 #[derive(Error, Debug)]
@@ -79,22 +200,60 @@ pub enum DispatcherError {
     ProcSpawnError(std::io::Error),
     #[error("Failed to spawn process (timeout)")]
     ProcSpawnTimeoutError,
+    #[error(
+        "Failed to spawn process: {0} (stop jobs or raise the open files limit with `ulimit -n`)"
+    )]
+    ResourceExhaustedError(std::io::Error),
     #[error("Failed to terminate child process: {0}")]
     KillError(std::io::Error),
+    #[error("Unknown signal `{0}`")]
+    InvalidSignalError(String),
+    #[error("Signal {0} not supported on this platform")]
+    SignalNotSupportedError(sysinfo::Signal),
     #[error("Job {0} not found")]
     JobNotFoundError(JobId),
     #[error("Service `{0}` not found")]
     ServiceNotFoundError(String),
+    #[error("Environment not recorded (start background process with `--record-env`)")]
+    EnvNotRecordedError,
+    #[error("Job {0} is not a cron job")]
+    NotCronJobError(JobId),
+    #[error("No terminated process of job {0}")]
+    NoExitError(JobId),
+    #[error("No jobs of group `{0}` found")]
+    GroupJobsNotFoundError(String),
+    #[error("Group `{0}` not found in justfile")]
+    GroupNotFoundError(String),
+    #[error("Service `{0}` not in group `{1}`")]
+    ServiceNotInGroupError(String, String),
+    #[error("Job {0} terminated during startup")]
+    StartupFailedError(JobId),
+    #[error("Services not running after {}", humantime::format_duration(*.0))]
+    StartupTimeoutError(Duration),
+    #[error("Job {0} not ready after {}", humantime::format_duration(*.1))]
+    NotReadyError(JobId, Duration),
+    #[error("Command timed out after {}", humantime::format_duration(*.0))]
+    CommandTimeoutError(Duration),
     #[error("Process exit code: {0}")]
     ProcExitError(i32),
+    #[error("Starting services from within job {0} is not allowed (use --nested to override)")]
+    NestedInvocationError(String),
+    #[error("Command `{0}` not allowed")]
+    CommandNotAllowedError(String),
     #[error("Empty command")]
     EmptyProcCommandError,
+    #[error("Invalid command `{0}`: {1}")]
+    InvalidCommandError(String, String),
     #[error(transparent)]
     JustfileError(#[from] JustfileError),
     #[error("Communication protocol error")]
     UnexpectedMessageError,
     #[error(transparent)]
     IpcClientError(#[from] IpcClientError),
+    #[error("Failed to write log archive: {0}")]
+    LogExportError(std::io::Error),
+    #[error("Invalid time `{0}` (expected e.g. `2024-06-01 12:00:00` or `5m`)")]
+    InvalidTimeError(String),
     #[error("Cron error: {0}")]
     CronError(#[from] cron::error::Error),
 }
@@ -104,16 +263,99 @@ impl Default for RestartInfo {
         RestartInfo {
             policy: Restart::OnFailure,
             wait_time: 50,
+            max_restarts: 10,
+            backoff: BackoffConfig::default(),
         }
     }
 }
 
+impl RestartInfo {
+    /// Delay before restarting a job, which was restarted `restarts` times within the backoff window
+    pub fn delay(&self, restarts: usize) -> Duration {
+        let factor = self.backoff.multiplier.powi(restarts.min(64) as i32);
+        let delay_ms = (self.wait_time as f64 * factor).min(self.backoff.max.as_millis() as f64);
+        Duration::from_millis(delay_ms as u64)
+    }
+}
+
+/// Command prefix switching stdio of C programs to line buffering.
+/// Output to pipes is block buffered by default, delaying log lines.
+const LINE_BUFFERED_CMD: [&str; 3] = ["stdbuf", "-oL", "-eL"];
+
+/// Command interpreting the joined arguments with the system shell
+pub fn shell_command(args: &[String]) -> Vec<String> {
+    let command = args.join(" ");
+    let shell: &[&str] = if cfg!(windows) {
+        &["cmd", "/C"]
+    } else {
+        &["sh", "-c"]
+    };
+    shell
+        .iter()
+        .map(|arg| arg.to_string())
+        .chain([command])
+        .collect()
+}
+
 impl JobInfo {
-    pub fn new_shell_job(args: Vec<String>) -> Self {
+    /// Job info of a `run` command, `None` for other commands
+    pub fn new_run_job(cmd: &ExecCommand) -> Option<Self> {
+        let ExecCommand::Run {
+            args,
+            log_streams,
+            no_buffer,
+            log_file,
+            shell,
+            restart,
+            restart_wait,
+            backoff,
+            env,
+            secret_env,
+            cwd,
+            ..
+        } = cmd
+        else {
+            return None;
+        };
+        let args = if *shell {
+            shell_command(args)
+        } else {
+            args.clone()
+        };
+        let mut job_info = JobInfo::new_shell_job(args, env.clone(), secret_env.clone());
+        job_info.log_streams = *log_streams;
+        job_info.no_buffer = *no_buffer;
+        job_info.workdir = cwd.clone();
+        job_info.log_file = log_file.clone();
+        if let Some(policy) = restart {
+            job_info.restart.policy = policy.clone();
+        }
+        if let Some(wait_time) = restart_wait {
+            job_info.restart.wait_time = *wait_time;
+        }
+        backoff.apply(&mut job_info.restart.backoff);
+        Some(job_info)
+    }
+    pub fn new_shell_job(
+        args: Vec<String>,
+        env: Vec<(String, String)>,
+        secret_env: Vec<(String, Secret)>,
+    ) -> Self {
         JobInfo {
             job_type: JobType::Shell,
             args,
+            env,
+            secret_env,
             entrypoint: None,
+            wrap: Vec::new(),
+            ready_cmd: Vec::new(),
+            readiness: None,
+            pre_stop: Vec::new(),
+            workdir: None,
+            log_streams: LogStreams::Both,
+            log_file: None,
+            no_buffer: false,
+            recipe: None,
             restart: RestartInfo {
                 policy: Restart::Never,
                 ..Default::default()
@@ -124,7 +366,40 @@ impl JobInfo {
         JobInfo {
             job_type: JobType::Cron(cron),
             args,
+            env: Vec::new(),
+            secret_env: Vec::new(),
+            entrypoint: None,
+            wrap: Vec::new(),
+            ready_cmd: Vec::new(),
+            readiness: None,
+            pre_stop: Vec::new(),
+            workdir: None,
+            log_streams: LogStreams::Both,
+            log_file: None,
+            no_buffer: false,
+            recipe: None,
+            restart: RestartInfo {
+                policy: Restart::Never,
+                ..Default::default()
+            },
+        }
+    }
+    pub fn new_cron_restart(cron: String, service: String) -> Self {
+        JobInfo {
+            job_type: JobType::CronRestart(cron, service),
+            args: Vec::new(),
+            env: Vec::new(),
+            secret_env: Vec::new(),
             entrypoint: None,
+            wrap: Vec::new(),
+            ready_cmd: Vec::new(),
+            readiness: None,
+            pre_stop: Vec::new(),
+            workdir: None,
+            log_streams: LogStreams::Both,
+            log_file: None,
+            no_buffer: false,
+            recipe: None,
             restart: RestartInfo {
                 policy: Restart::Never,
                 ..Default::default()
@@ -135,14 +410,59 @@ impl JobInfo {
         JobInfo {
             job_type: JobType::Service(service.clone()),
             args: vec!["just".to_string(), service], // TODO: exclude entrypoint
+            env: Vec::new(),
+            secret_env: Vec::new(),
             entrypoint: Some("just".to_string()),
+            wrap: Vec::new(),
+            ready_cmd: Vec::new(),
+            readiness: None,
+            pre_stop: Vec::new(),
+            workdir: None,
+            log_streams: LogStreams::Both,
+            log_file: None,
+            no_buffer: false,
+            recipe: None,
             restart: RestartInfo::default(),
         }
     }
+    /// Command arguments including wrapper prefix
+    pub fn spawn_args(&self) -> Vec<String> {
+        let line_buffered = LINE_BUFFERED_CMD
+            .iter()
+            .filter(|_| self.no_buffer)
+            .map(|arg| arg.to_string());
+        line_buffered
+            .chain(self.wrap.iter().chain(&self.args).cloned())
+            .collect()
+    }
+    /// Environment variables including secret values
+    pub fn spawn_env(&self) -> Vec<(String, String)> {
+        let secrets = self
+            .secret_env
+            .iter()
+            .map(|(key, value)| (key.clone(), value.0.clone()));
+        let unbuffered = self
+            .no_buffer
+            .then(|| ("PYTHONUNBUFFERED".to_string(), "1".to_string()));
+        self.env
+            .iter()
+            .cloned()
+            .chain(unbuffered)
+            .chain(secrets)
+            .collect()
+    }
+    /// Job info without secret values
+    pub fn redacted(&self) -> Self {
+        let mut info = self.clone();
+        for (_key, value) in info.secret_env.iter_mut() {
+            *value = Secret::redacted();
+        }
+        info
+    }
 }
 
 impl Dispatcher<'_> {
-    pub fn create() -> Dispatcher<'static> {
+    pub fn create(args: &DaemonCli) -> Dispatcher<'static> {
         let procs = Arc::new(Mutex::new(Vec::new()));
         let scheduler = Arc::new(Mutex::new(JobScheduler::new()));
 
@@ -150,49 +470,283 @@ impl Dispatcher<'_> {
         let _handle = thread::spawn(move || cron_scheduler(scheduler_spawn));
 
         let (send, recv) = mpsc::channel();
-        let send_spawn = send.clone();
+        let runner_ctx = RunnerContext {
+            channel: send,
+            pending_exits: Arc::new(AtomicUsize::new(0)),
+            log_lines: args.log_lines.0,
+            log_budget: args
+                .log_memory
+                .map(|max_bytes| Arc::new(LogBudget::new(max_bytes.as_u64()))),
+            allowed_commands: (!args.allow_commands.is_empty())
+                .then(|| Arc::new(args.allow_commands.clone())),
+            clean_env: args.clean_env,
+            record_env: args.record_env,
+            runs: Arc::new(Mutex::new(HashMap::new())),
+            last_exit: Arc::new(Mutex::new(HashMap::new())),
+            restarts: Arc::new(Mutex::new(HashMap::new())),
+            termination_log: Arc::new(Mutex::new(HashMap::new())),
+            audit_log: args.audit_file.as_ref().and_then(|path| {
+                AuditLog::open(path)
+                    .map_err(|e| error!("Failed to open audit file {}: {e}", path.display()))
+                    .ok()
+                    .map(Arc::new)
+            }),
+        };
+        let runner_ctx_spawn = runner_ctx.clone();
         let procs_spawn = procs.clone();
-        let _watcher = thread::spawn(move || child_watcher(procs_spawn, send_spawn, recv));
+        let no_restart = args.no_restart;
+        let _watcher =
+            thread::spawn(move || child_watcher(procs_spawn, runner_ctx_spawn, recv, no_restart));
+
+        #[cfg(feature = "metrics")]
+        if let Some(addr) = args.metrics_addr {
+            if let Err(e) = crate::start_metrics_listener(addr, procs.clone()) {
+                error!("Failed to serve metrics on {addr}: {e}");
+            }
+        }
 
         let system = System::new_with_specifics(
             RefreshKind::new().with_processes(ProcessRefreshKind::new()),
         );
 
-        Dispatcher {
+        let mut dispatcher = Dispatcher {
             jobs: BTreeMap::new(),
             last_job_id: 0,
             cronjobs: HashMap::new(),
             procs,
             scheduler,
             system,
-            channel: send,
+            runner_ctx,
+            started: Local::now(),
+        };
+        dispatcher.restore_state();
+        dispatcher
+    }
+    /// Persist jobs for restoring them after a restart of the background process
+    fn save_state(&self) {
+        let state = DispatcherState {
+            last_job_id: self.last_job_id,
+            jobs: self.jobs.clone(),
+        };
+        let res = state_file_path().and_then(|path| {
+            write_state_file(&path, &state)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+        });
+        if let Err(e) = res {
+            error!("Failed to write state file: {e}");
+        }
+    }
+    /// Restore jobs of a previous background process.
+    /// Cron jobs are scheduled again and services with restart policy `always` are respawned.
+    fn restore_state(&mut self) {
+        let path = match state_file_path() {
+            Ok(path) => path,
+            Err(e) => {
+                error!("Failed to access state file: {e}");
+                return;
+            }
+        };
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                error!("Failed to read state file {}: {e}", path.display());
+                return;
+            }
+        };
+        let state: DispatcherState = match serde_json::from_str(&json) {
+            Ok(state) => state,
+            Err(e) => {
+                error!("Ignoring invalid state file {}: {e}", path.display());
+                return;
+            }
+        };
+        self.last_job_id = state.last_job_id;
+        self.jobs = state
+            .jobs
+            .into_iter()
+            .filter(|(_job_id, job)| match job.job_type {
+                JobType::Service(_) => matches!(job.restart.policy, Restart::Always),
+                JobType::Cron(_) | JobType::CronRestart(_, _) => true,
+                JobType::Shell => false,
+            })
+            .collect();
+        let (services, scheduled): (Vec<JobId>, Vec<JobId>) = self
+            .jobs
+            .keys()
+            .partition(|job_id| matches!(self.jobs[job_id].job_type, JobType::Service(_)));
+        // Services first, since scheduled restarts refer to them
+        for job_id in services {
+            match self.spawn_job(job_id, self.runner_ctx.log_lines) {
+                Ok(()) => info!("Job {job_id} restored"),
+                Err(e) => error!("Failed to restore job {job_id}: {e}"),
+            }
+        }
+        for job_id in scheduled {
+            match self.schedule_job(job_id) {
+                Ok(()) => info!("Job {job_id} restored"),
+                Err(e) => {
+                    error!("Failed to restore job {job_id}: {e}");
+                    self.jobs.remove(&job_id);
+                }
+            }
         }
     }
-    pub fn exec_command(&mut self, cmd: ExecCommand) -> Message {
+    pub fn exec_command(
+        &mut self,
+        cmd: ExecCommand,
+        mut stream: Option<&mut IpcStream>,
+    ) -> Message {
         info!("Executing `{cmd:?}`");
+        let wait = match &cmd {
+            ExecCommand::Run {
+                wait: true, quiet, ..
+            } => Some(WaitMode::Output { quiet: *quiet }),
+            ExecCommand::Run { capture: true, .. } => Some(WaitMode::Capture),
+            _ => None,
+        };
         let res = match cmd {
-            ExecCommand::Run { args } => self.run(&args),
+            ExecCommand::Run {
+                wait,
+                ref group_env,
+                capture,
+                ..
+            } => match JobInfo::new_run_job(&cmd) {
+                Some(mut job_info) => {
+                    let res = match group_env {
+                        Some(group) => self.apply_group_env(&mut job_info, group),
+                        None => Ok(()),
+                    };
+                    res.and_then(|()| self.run(job_info, wait, capture))
+                }
+                None => Err(DispatcherError::EmptyProcCommandError),
+            },
             ExecCommand::Runat { at, args } => self.run_at(&at, &args),
-            ExecCommand::Start { service } => self.start(&service),
-            ExecCommand::Up { group } => self.up(&group),
+            ExecCommand::Trigger { job_id } => self.trigger(job_id),
+            ExecCommand::RestartAt { at, service } => self.restart_at(&at, &service),
+            ExecCommand::Start { service, options } => self.start(&service, &options),
+            ExecCommand::Up {
+                group,
+                selection,
+                pacing,
+                wait,
+                timeout,
+                options,
+                ..
+            } => self.up(
+                &group,
+                &selection,
+                &pacing,
+                &options,
+                wait.then_some(timeout),
+                stream.as_deref_mut(),
+            ),
         };
+        self.save_state();
         match res {
             Err(e) => {
                 error!("{e}");
                 Message::Err(format!("{e}"))
             }
-            Ok(job_ids) => Message::JobsStarted(job_ids),
+            Ok(job_ids) => match (wait, stream, job_ids.first()) {
+                (Some(mode), Some(stream), Some(job_id)) => {
+                    match self.wait(*job_id, mode, stream) {
+                        Ok(code) => Message::ExitCode(code),
+                        Err(e) => {
+                            error!("{e}");
+                            Message::Err(format!("{e}"))
+                        }
+                    }
+                }
+                _ => Message::JobsStarted(job_ids),
+            },
         }
     }
     pub fn cli_command(&mut self, cmd: CliCommand, stream: &mut IpcStream) {
         info!("Executing `{cmd:?}`");
         let res = match cmd {
-            CliCommand::Stop { job_id } => self.stop(job_id),
-            CliCommand::Down { group } => self.down(&group),
-            CliCommand::Ps => self.ps(stream),
-            CliCommand::Jobs => self.jobs(stream),
-            CliCommand::Logs { job_or_service } => self.log(job_or_service, stream),
-            CliCommand::Exit => std::process::exit(0),
+            CliCommand::Stop {
+                job_id,
+                tree,
+                timeout,
+            } => self.stop(job_id, tree, timeout),
+            CliCommand::Reload { service } => self.reload(&service),
+            CliCommand::Restart { job_or_service } => match self.restart(&job_or_service) {
+                Ok(job_ids) => {
+                    let _ = stream.send_message(&Message::JobsStarted(job_ids));
+                    return;
+                }
+                Err(e) => Err(e),
+            },
+            CliCommand::Set { job_id, log_buffer } => self.set(job_id, log_buffer),
+            CliCommand::Pause { job_id } => self.pause(job_id, true),
+            CliCommand::Resume { job_id } => self.pause(job_id, false),
+            CliCommand::Signal {
+                job_or_service,
+                signal,
+            } => self.signal(&job_or_service, &signal),
+            CliCommand::Hold { job_id } => self.hold(job_id, true),
+            CliCommand::Unhold { job_id } => self.hold(job_id, false),
+            CliCommand::Down { group, selection } => self.down(&group, &selection),
+            CliCommand::Ps {
+                no_cpu,
+                cpu_interval,
+                ..
+            } => {
+                let interval = if no_cpu {
+                    Duration::ZERO
+                } else {
+                    cpu_interval.unwrap_or(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL)
+                };
+                self.ps(interval, stream)
+            }
+            CliCommand::Jobs { .. } => self.jobs(stream),
+            CliCommand::Inspect { job_id, env } => self.inspect(job_id, env, stream),
+            CliCommand::Why { job_id } => self.why(job_id, stream),
+            CliCommand::Renumber => self.renumber().and_then(|renumbered| {
+                let _ = stream.send_message(&Message::JobsRenumbered(renumbered));
+                self.jobs(stream)
+            }),
+            CliCommand::Status => self.status(stream),
+            CliCommand::Logs {
+                job_or_service,
+                group,
+                export,
+                tail,
+                since,
+                stdout,
+                stderr,
+                ..
+            } => {
+                let streams = match (stdout, stderr) {
+                    (true, false) => LogStreams::Stdout,
+                    (false, true) => LogStreams::Stderr,
+                    _ => LogStreams::Both,
+                };
+                let selection = LogSelection {
+                    job_or_service,
+                    group,
+                    streams,
+                };
+                match since.as_deref().map(parse_since).transpose() {
+                    Ok(since) => self.log(selection, export.is_none(), None, tail, since, stream),
+                    Err(e) => Err(e),
+                }
+            }
+            CliCommand::Watch { group, interval } => {
+                let selection = LogSelection {
+                    job_or_service: None,
+                    group: Some(group),
+                    streams: LogStreams::Both,
+                };
+                self.log(selection, true, Some(interval), None, None, stream)
+            }
+            // Handled by client
+            CliCommand::Groups
+            | CliCommand::CronPreview { .. }
+            | CliCommand::Doctor
+            | CliCommand::Batch => Err(DispatcherError::UnexpectedMessageError),
+            CliCommand::Exit => self.exit(),
         };
         if let Err(e) = &res {
             error!("{e}");
@@ -204,14 +758,34 @@ impl Dispatcher<'_> {
         self.jobs.insert(self.last_job_id, job);
         self.last_job_id
     }
-    fn spawn_info(&self, job_id: JobId) -> Result<JobSpawnInfo<'_>, DispatcherError> {
+    fn spawn_info(&self, job_id: JobId) -> Result<JobSpawnInfo, DispatcherError> {
         let job = self
             .jobs
             .get(&job_id)
             .ok_or(DispatcherError::JobNotFoundError(job_id))?;
+        let mut args = job.spawn_args();
+        let mut env = job.spawn_env();
+        // Placeholders of services are resolved at start time
+        if matches!(job.job_type, JobType::Service(_)) {
+            let vars: HashMap<String, String> = self
+                .runner_ctx
+                .effective_env(job_id, &env)
+                .into_iter()
+                .collect();
+            for arg in args.iter_mut() {
+                *arg = interpolate_env(arg, &vars);
+            }
+            for (_key, value) in env.iter_mut() {
+                *value = interpolate_env(value, &vars);
+            }
+        }
         Ok(JobSpawnInfo {
             job_id,
-            args: &job.args,
+            args,
+            env,
+            workdir: job.workdir.clone(),
+            log_streams: job.log_streams,
+            log_file: job.log_file.clone(),
             restart_info: job.restart.clone(),
         })
     }
@@ -222,15 +796,80 @@ impl Dispatcher<'_> {
             .find(|(_id, info)| matches!(&info.job_type, JobType::Service(name) if name == service))
             .map(|(id, _info)| *id)
     }
-    fn run(&mut self, args: &[String]) -> Result<Vec<JobId>, DispatcherError> {
-        let job_info = JobInfo::new_shell_job(args.to_vec());
+    /// Find job by job id or service name
+    fn resolve_job(&self, job_or_service: &str) -> Result<JobId, DispatcherError> {
+        if let Ok(job_id) = JobId::from_str(job_or_service) {
+            if self.jobs.contains_key(&job_id) {
+                Ok(job_id)
+            } else {
+                Err(DispatcherError::JobNotFoundError(job_id))
+            }
+        } else {
+            self.find_job(job_or_service)
+                .ok_or(DispatcherError::ServiceNotFoundError(
+                    job_or_service.to_string(),
+                ))
+        }
+    }
+    /// Set environment and working directory of the services of a group.
+    /// Variables passed with the command take precedence.
+    fn apply_group_env(&self, job: &mut JobInfo, group: &str) -> Result<(), DispatcherError> {
+        let justfile = Justfile::parse_with_retry()?;
+        if justfile.group_recipes(group).is_empty() {
+            return Err(DispatcherError::GroupNotFoundError(group.to_string()));
+        }
+        let mut env = justfile.group_env(group);
+        // Resolve placeholders like for services, with the id the job will get
+        let vars: HashMap<String, String> = self
+            .runner_ctx
+            .effective_env(self.last_job_id + 1, &env)
+            .into_iter()
+            .collect();
+        for (_key, value) in env.iter_mut() {
+            *value = interpolate_env(value, &vars);
+        }
+        env.append(&mut job.env);
+        job.env = env;
+        if job.workdir.is_none() {
+            job.workdir = justfile.working_directory();
+        }
+        Ok(())
+    }
+    fn run(
+        &mut self,
+        job_info: JobInfo,
+        wait: bool,
+        capture: bool,
+    ) -> Result<Vec<JobId>, DispatcherError> {
         let job_id = self.add_job(job_info);
-        self.spawn_job(job_id)?;
+        // Captured output is buffered completely
+        let max_log_lines = self.runner_ctx.log_lines.filter(|_| !capture);
+        match self.spawn_job(job_id, max_log_lines) {
+            // Exit code is returned after waiting
+            Err(DispatcherError::ProcExitError(_)) if wait || capture => {}
+            res => res?,
+        }
         Ok(vec![job_id])
     }
-    fn spawn_job(&mut self, job_id: JobId) -> Result<(), DispatcherError> {
+    fn spawn_job(
+        &mut self,
+        job_id: JobId,
+        max_log_lines: Option<usize>,
+    ) -> Result<(), DispatcherError> {
         let job = self.spawn_info(job_id)?;
-        let child = Runner::spawn(job.job_id, job.args, job.restart_info, self.channel.clone())?;
+        let child = Runner::spawn(
+            job.job_id,
+            &job.args,
+            &job.env,
+            job.workdir.as_deref(),
+            job.restart_info,
+            OutputOptions {
+                max_lines: max_log_lines,
+                streams: job.log_streams,
+                log_file: job.log_file,
+            },
+            &self.runner_ctx,
+        )?;
         self.procs.lock().expect("lock").push(child);
         // Wait for startup failure
         thread::sleep(Duration::from_millis(10));
@@ -244,250 +883,1169 @@ impl Dispatcher<'_> {
         Ok(())
     }
     /// Stop job
-    fn stop(&mut self, job_id: JobId) -> Result<(), DispatcherError> {
-        if let Some(uuid) = self.cronjobs.remove(&job_id) {
-            info!("Removing cron job {job_id}");
-            self.scheduler.lock().expect("lock").remove(uuid);
+    fn stop(
+        &mut self,
+        job_id: JobId,
+        tree: bool,
+        grace_period: Duration,
+    ) -> Result<(), DispatcherError> {
+        self.stop_jobs(&[job_id], tree, grace_period)
+    }
+    /// Stop jobs, waiting for their processes in parallel
+    fn stop_jobs(
+        &mut self,
+        job_ids: &[JobId],
+        tree: bool,
+        grace_period: Duration,
+    ) -> Result<(), DispatcherError> {
+        for &job_id in job_ids {
+            if self.unschedule_job(job_id) {
+                info!("Removing cron job {job_id}");
+            }
+            self.run_pre_stop(job_id);
         }
+        let terminations = self.send_term(job_ids, AuditEvent::Stop, tree)?;
+        Termination::wait_all(terminations, grace_period);
+        let mut missing = job_ids
+            .iter()
+            .filter(|job_id| self.jobs.remove(job_id).is_none());
+        let res = match missing.next() {
+            Some(job_id) => Err(DispatcherError::JobNotFoundError(*job_id)),
+            None => Ok(()),
+        };
+        self.save_state();
+        res
+    }
+    /// Send SIGTERM to running processes of jobs.
+    /// The process lock is not held while waiting for termination.
+    fn send_term(
+        &self,
+        job_ids: &[JobId],
+        event: AuditEvent,
+        tree: bool,
+    ) -> Result<Vec<Termination>, DispatcherError> {
+        let mut terminations = Vec::new();
         for child in self
             .procs
             .lock()
             .expect("lock")
             .iter_mut()
-            .filter(|child| child.info.job_id == job_id)
+            .filter(|child| job_ids.contains(&child.info.job_id))
         {
+            // Also prevents pending restarts of terminated processes
+            child.user_terminated = true;
             if child.is_running() {
-                child.user_terminated = true;
-                child.terminate().map_err(DispatcherError::KillError)?;
+                self.runner_ctx.audit(event, &child.info, None);
+                terminations.push(child.send_term(tree).map_err(DispatcherError::KillError)?);
             }
         }
-        if self.jobs.remove(&job_id).is_some() {
-            Ok(())
+        Ok(terminations)
+    }
+    /// Terminate running processes of a job and spawn it again with the same job id
+    fn restart(&mut self, job_or_service: &str) -> Result<Vec<JobId>, DispatcherError> {
+        let job_id = self.resolve_job(job_or_service)?;
+        self.run_pre_stop(job_id);
+        let terminations = self.send_term(&[job_id], AuditEvent::Restart, false)?;
+        Termination::wait_all(terminations, DEFAULT_GRACE_PERIOD);
+        wait_terminated(&self.procs, job_id);
+        if let Some(JobType::Service(service)) = self.jobs.get(&job_id).map(|job| &job.job_type) {
+            let service = service.clone();
+            // Start with the current recipe, keeping the service options
+            self.start(&service, &ServiceOptions::default())
         } else {
-            Err(DispatcherError::JobNotFoundError(job_id))
+            self.spawn_job(job_id, self.runner_ctx.log_lines)?;
+            Ok(vec![job_id])
         }
     }
-    /// Add cron job
-    fn run_at(&mut self, cron: &str, args: &[String]) -> Result<Vec<JobId>, DispatcherError> {
-        let job_info = JobInfo::new_cron_job(cron.to_string(), args.to_vec());
-        let restart_info = job_info.restart.clone();
-        let job_id = self.add_job(job_info);
-        let job_args = args.to_vec();
-        let procs = self.procs.clone();
-        let channel = self.channel.clone();
-        let uuid = self
-            .scheduler
-            .lock()
-            .expect("lock")
-            .add(job_scheduler::Job::new(cron.parse()?, move || {
-                let child = Runner::spawn(job_id, &job_args, restart_info.clone(), channel.clone())
-                    .unwrap();
-                procs.lock().expect("lock").push(child);
-            }));
-        self.cronjobs.insert(job_id, uuid);
-        Ok(vec![job_id])
+    /// Stop all jobs and exit background process
+    fn exit(&mut self) -> ! {
+        let job_ids: Vec<JobId> = self.jobs.keys().copied().collect();
+        for job_id in job_ids {
+            self.run_pre_stop(job_id);
+        }
+        let mut procs = self.procs.lock().expect("lock");
+        // Terminate all processes within a common grace period
+        thread::scope(|scope| {
+            for child in procs.iter_mut() {
+                if child.is_running() {
+                    child.user_terminated = true;
+                    self.runner_ctx.audit(AuditEvent::Stop, &child.info, None);
+                    scope.spawn(|| child.terminate_gracefully(DEFAULT_GRACE_PERIOD, false));
+                }
+            }
+        });
+        // Jobs stopped on purpose are not restored
+        if let Err(e) = state_file_path().and_then(fs::remove_file) {
+            if e.kind() != io::ErrorKind::NotFound {
+                error!("Failed to remove state file: {e}");
+            }
+        }
+        std::process::exit(0)
     }
-    /// Start service (just recipe)
-    fn start(&mut self, service: &str) -> Result<Vec<JobId>, DispatcherError> {
-        // Find existing job or add new
-        let job_id = self
-            .find_job(service)
-            .unwrap_or_else(|| self.add_job(JobInfo::new_service(service.to_string())));
-        // Check for existing process for this service
+    /// Execute pre-stop command of a running job. Failures are logged only.
+    fn run_pre_stop(&mut self, job_id: JobId) {
+        let Some(job) = self
+            .jobs
+            .get(&job_id)
+            .filter(|job| !job.pre_stop.is_empty())
+        else {
+            return;
+        };
         let running = self
             .procs
             .lock()
             .expect("lock")
             .iter_mut()
             .any(|child| child.info.job_id == job_id && child.is_running());
-        if running {
-            Ok(vec![])
-        } else {
-            self.spawn_job(job_id)?;
-            Ok(vec![job_id])
+        if !running {
+            return;
+        }
+        info!("Executing pre-stop command of job {job_id}");
+        match Runner::check(&job.pre_stop, Some(PRE_STOP_TIMEOUT), &self.runner_ctx) {
+            Ok(true) => {}
+            Ok(false) => error!("Pre-stop command of job {job_id} failed"),
+            Err(e) => error!("Pre-stop command of job {job_id} failed: {e}"),
         }
     }
-    /// Start service group (all just repipes in group)
-    fn up(&mut self, group: &str) -> Result<Vec<JobId>, DispatcherError> {
-        let mut job_ids = Vec::new();
-        let justfile = Justfile::parse()?;
-        let recipes = justfile.group_recipes(group);
-        for service in recipes {
-            let ids = self.start(&service)?;
-            job_ids.extend(ids);
+    /// Change settings of job processes
+    fn set(&mut self, job_id: JobId, log_buffer: Option<usize>) -> Result<(), DispatcherError> {
+        if !self.jobs.contains_key(&job_id) {
+            return Err(DispatcherError::JobNotFoundError(job_id));
         }
-        Ok(job_ids)
+        for child in self
+            .procs
+            .lock()
+            .expect("lock")
+            .iter()
+            .filter(|child| child.info.job_id == job_id)
+        {
+            if let (Some(max_len), Ok(mut output)) = (log_buffer, child.output.lock()) {
+                output.set_max_len(max_len);
+            }
+        }
+        Ok(())
     }
-    /// Stop service group
-    fn down(&mut self, group: &str) -> Result<(), DispatcherError> {
-        let mut job_ids = Vec::new();
-        let justfile = Justfile::parse()?;
-        let recipes = justfile.group_recipes(group);
-        for service in recipes {
-            self.jobs
-                .iter()
-                .filter(|(_id, info)| matches!(&info.job_type, JobType::Service(name) if *name == service))
-                .for_each(|(id, _info)| job_ids.push(*id));
+    /// Pause or resume running processes of job
+    fn pause(&mut self, job_id: JobId, pause: bool) -> Result<(), DispatcherError> {
+        if !self.jobs.contains_key(&job_id) {
+            return Err(DispatcherError::JobNotFoundError(job_id));
         }
-        for job_id in job_ids {
-            self.stop(job_id)?;
+        for child in self
+            .procs
+            .lock()
+            .expect("lock")
+            .iter_mut()
+            .filter(|child| child.info.job_id == job_id)
+        {
+            if child.is_running() {
+                child.pause(pause)?;
+            }
         }
         Ok(())
     }
-    /// Return info about running and finished processes
-    fn ps(&mut self, stream: &mut IpcStream) -> Result<(), DispatcherError> {
-        // Update system info
-        // For accurate CPU usage, a process needs to be refreshed twice
-        // https://docs.rs/sysinfo/latest/i686-pc-windows-msvc/sysinfo/struct.Process.html#method.cpu_usage
-        let ts = Local::now();
-        self.system.refresh_processes_specifics(
-            ProcessesToUpdate::All,
-            true,
-            ProcessRefreshKind::new().with_cpu(),
-        );
-        // Collect pids and child pids
-        let pids: Vec<sysinfo::Pid> = self
+    /// Send signal to running processes of job, which keep being supervised
+    fn signal(&mut self, job_or_service: &str, signal: &str) -> Result<(), DispatcherError> {
+        let job_id = self.resolve_job(job_or_service)?;
+        let signal = parse_signal(signal)?;
+        for child in self
             .procs
             .lock()
             .expect("lock")
-            .iter()
-            .flat_map(|proc| {
-                let parent_pid = sysinfo::Pid::from(proc.info.pid as usize);
-                self.system
-                    .processes()
-                    .iter()
-                    .filter(move |(_pid, process)| {
-                        process.parent().unwrap_or(0.into()) == parent_pid
-                    })
-                    .map(|(pid, _process)| *pid)
-                    .chain([parent_pid])
-            })
-            .collect();
-        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL); // 200ms
-        let duration = (Local::now() - ts).num_milliseconds();
-        fn per_second(value: u64, ms: i64) -> u64 {
-            (value as f64 * 1000.0 / ms as f64) as u64
-        }
-        self.system.refresh_processes_specifics(
-            ProcessesToUpdate::Some(&pids),
-            true,
-            ProcessRefreshKind::new()
-                .with_cpu()
-                .with_disk_usage()
-                .with_memory(),
-        );
-
-        let mut proc_infos = Vec::new();
-        for child in &mut self.procs.lock().expect("lock").iter_mut().rev() {
-            let parent_pid = sysinfo::Pid::from(child.info.pid as usize);
-            // CPU usage has to be measured from just child processs
-            // For tasks spawning child processes, we should consider the whole process subtree!
-            let main_pid = if child.info.program() == "just" {
-                self.system
-                    .processes()
-                    .iter()
-                    .find(|(_pid, process)| {
-                        process.parent().unwrap_or(0.into()) == parent_pid
-                            && process.name() != "ctrl-c"
-                    })
-                    .map(|(pid, _process)| *pid)
-                    .unwrap_or(parent_pid)
-            } else {
-                parent_pid
-            };
-            if let Some(process) = self.system.process(main_pid) {
-                child.info.cpu = process.cpu_usage();
-                child.info.memory = process.memory();
-                child.info.virtual_memory = process.virtual_memory();
-                let disk = process.disk_usage();
-                child.info.total_written_bytes = disk.total_written_bytes;
-                child.info.written_bytes = per_second(disk.written_bytes, duration);
-                child.info.total_read_bytes = disk.total_read_bytes;
-                child.info.read_bytes = per_second(disk.read_bytes, duration);
-            } else {
-                child.info.cpu = 0.0;
-                child.info.memory = 0;
-                child.info.virtual_memory = 0;
-                child.info.written_bytes = 0;
-                child.info.read_bytes = 0;
+            .iter_mut()
+            .filter(|child| child.info.job_id == job_id)
+        {
+            if child.is_running() {
+                child.signal(signal)?;
             }
-            let info = child.update_proc_state();
-            proc_infos.push(info.clone());
         }
-        stream.send_message(&Message::PsInfo(proc_infos))?;
         Ok(())
     }
-    /// Return info about jobs
-    fn jobs(&mut self, stream: &mut IpcStream) -> Result<(), DispatcherError> {
-        let mut job_infos = Vec::new();
-        for (id, info) in self.jobs.iter().rev() {
-            job_infos.push(Job {
-                id: *id,
-                info: info.clone(),
-            });
+    /// Suppress or enable restarts of job processes
+    fn hold(&mut self, job_id: JobId, hold: bool) -> Result<(), DispatcherError> {
+        if !self.jobs.contains_key(&job_id) {
+            return Err(DispatcherError::JobNotFoundError(job_id));
+        }
+        // Terminated processes included, to cancel pending restarts
+        for child in self
+            .procs
+            .lock()
+            .expect("lock")
+            .iter_mut()
+            .filter(|child| child.info.job_id == job_id)
+        {
+            child.held = hold;
         }
-        stream.send_message(&Message::JobInfo(job_infos))?;
         Ok(())
     }
-    /// Return log lines
-    fn log(
-        &mut self,
-        job_or_service: Option<String>,
-        stream: &mut IpcStream,
-    ) -> Result<(), DispatcherError> {
-        let mut job_id_filter = None;
-        if let Some(job_or_service) = job_or_service {
-            if let Ok(job_id) = JobId::from_str(&job_or_service) {
-                if self.jobs.contains_key(&job_id) {
-                    job_id_filter = Some(job_id);
-                } else {
-                    return Err(DispatcherError::JobNotFoundError(job_id));
-                }
-            } else {
-                job_id_filter = Some(
-                    self.find_job(&job_or_service)
-                        .ok_or(DispatcherError::ServiceNotFoundError(job_or_service))?,
-                );
-            }
+    /// Add cron job
+    /// Spawn one execution of a cron job outside of its schedule
+    fn trigger(&mut self, job_id: JobId) -> Result<Vec<JobId>, DispatcherError> {
+        let job = self
+            .jobs
+            .get(&job_id)
+            .ok_or(DispatcherError::JobNotFoundError(job_id))?;
+        if !matches!(job.job_type, JobType::Cron(_)) {
+            return Err(DispatcherError::NotCronJobError(job_id));
         }
-
-        let mut last_seen_ts: HashMap<Pid, DateTime<Local>> = HashMap::new();
-        'logwait: loop {
-            // Collect log entries from child proceses
-            let mut log_lines = Vec::new();
-            for child in self.procs.lock().expect("lock").iter_mut() {
-                if let Ok(output) = child.output.lock() {
-                    let last_seen = last_seen_ts
-                        .entry(child.proc.id())
-                        .or_insert(Local.timestamp_millis_opt(0).single().expect("ts"));
-                    for entry in output.lines_since(last_seen) {
-                        if let Some(job_id) = job_id_filter {
-                            if entry.job_id != job_id {
-                                continue;
-                            }
+        let job = self.spawn_info(job_id)?;
+        if let Some(exe) = job.args.first() {
+            self.runner_ctx.check_allowed(exe)?;
+        }
+        let mut child = Runner::spawn(
+            job.job_id,
+            &job.args,
+            &job.env,
+            job.workdir.as_deref(),
+            job.restart_info,
+            OutputOptions {
+                max_lines: self.runner_ctx.log_lines,
+                streams: job.log_streams,
+                log_file: job.log_file,
+            },
+            &self.runner_ctx,
+        )?;
+        child.info.manual = true;
+        info!("Job {job_id} triggered manually (pid {})", child.info.pid);
+        self.procs.lock().expect("lock").push(child);
+        Ok(vec![job_id])
+    }
+    fn run_at(&mut self, cron: &str, args: &[String]) -> Result<Vec<JobId>, DispatcherError> {
+        parse_schedule(cron)?;
+        let exe = args.first().ok_or(DispatcherError::EmptyProcCommandError)?;
+        self.runner_ctx.check_allowed(exe)?;
+        let job_id = self.add_job(JobInfo::new_cron_job(cron.to_string(), args.to_vec()));
+        self.schedule_job(job_id)?;
+        Ok(vec![job_id])
+    }
+    /// Add cron job restarting a running service
+    fn restart_at(&mut self, cron: &str, service: &str) -> Result<Vec<JobId>, DispatcherError> {
+        parse_schedule(cron)?;
+        self.find_job(service)
+            .ok_or(DispatcherError::ServiceNotFoundError(service.to_string()))?;
+        let job_id = self.add_job(JobInfo::new_cron_restart(
+            cron.to_string(),
+            service.to_string(),
+        ));
+        self.schedule_job(job_id)?;
+        Ok(vec![job_id])
+    }
+    /// Add cron job or scheduled restart to scheduler
+    fn schedule_job(&mut self, job_id: JobId) -> Result<(), DispatcherError> {
+        let job_type = self
+            .jobs
+            .get(&job_id)
+            .ok_or(DispatcherError::JobNotFoundError(job_id))?
+            .job_type
+            .clone();
+        let procs = self.procs.clone();
+        let runner_ctx = self.runner_ctx.clone();
+        let stopping = Arc::new(AtomicBool::new(false));
+        let stop_requested = stopping.clone();
+        let job = match job_type {
+            JobType::Cron(cron) => {
+                let schedule = parse_schedule(&cron)?;
+                let job = self.spawn_info(job_id)?;
+                job_scheduler::Job::new(schedule, move || {
+                    if stop_requested.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    match Runner::spawn(
+                        job_id,
+                        &job.args,
+                        &job.env,
+                        job.workdir.as_deref(),
+                        job.restart_info.clone(),
+                        OutputOptions {
+                            max_lines: runner_ctx.log_lines,
+                            streams: job.log_streams,
+                            log_file: job.log_file.clone(),
+                        },
+                        &runner_ctx,
+                    ) {
+                        Ok(child) => procs.lock().expect("lock").push(child),
+                        Err(e @ DispatcherError::ResourceExhaustedError(_)) => {
+                            warn!("Skipping run of cron job {job_id}: {e}");
                         }
-                        log_lines.push(entry.clone());
+                        Err(e) => error!("Failed to spawn cron job {job_id}: {e}"),
                     }
-                }
+                })
             }
-
-            if log_lines.is_empty() {
-                // Exit when client is disconnected
-                stream.alive()?;
-            } else {
-                log_lines.sort_by_key(|entry| entry.ts);
-                for entry in log_lines {
-                    if stream.send_message(&Message::LogLine(entry)).is_err() {
+            JobType::CronRestart(cron, service) => {
+                let schedule = parse_schedule(&cron)?;
+                let service_job_id = self
+                    .find_job(&service)
+                    .ok_or(DispatcherError::ServiceNotFoundError(service.clone()))?;
+                let job = self.spawn_info(service_job_id)?;
+                job_scheduler::Job::new(schedule, move || {
+                    if stop_requested.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    info!("Scheduled restart of service `{service}`");
+                    if let Err(e) = restart_procs(&procs, &job, &runner_ctx) {
+                        error!("Failed to restart service `{service}`: {e}");
+                    }
+                })
+            }
+            JobType::Shell | JobType::Service(_) => return Ok(()),
+        };
+        let uuid = self.scheduler.lock().expect("lock").add(job);
+        self.cronjobs.insert(job_id, CronEntry { uuid, stopping });
+        Ok(())
+    }
+    /// Next execution time of a scheduled job
+    fn next_run(&self, job_id: JobId) -> Option<DateTime<Local>> {
+        if !self.cronjobs.contains_key(&job_id) {
+            return None;
+        }
+        match &self.jobs.get(&job_id)?.job_type {
+            JobType::Cron(cron) | JobType::CronRestart(cron, _) => {
+                parse_schedule(cron).ok()?.upcoming(Local).next()
+            }
+            JobType::Shell | JobType::Service(_) => None,
+        }
+    }
+    /// Remove job from scheduler. Returns false for unscheduled jobs.
+    ///
+    /// The scheduler lock is held while scheduled closures are executed,
+    /// so instances spawned by a concurrent tick are registered in `procs`
+    /// when this returns, and no further instance is spawned.
+    fn unschedule_job(&mut self, job_id: JobId) -> bool {
+        let Some(entry) = self.cronjobs.remove(&job_id) else {
+            return false;
+        };
+        entry.stopping.store(true, Ordering::SeqCst);
+        self.scheduler.lock().expect("lock").remove(entry.uuid);
+        true
+    }
+    /// Start service (just recipe)
+    fn start(
+        &mut self,
+        service: &str,
+        options: &ServiceOptions,
+    ) -> Result<Vec<JobId>, DispatcherError> {
+        let justfile = Justfile::parse_with_retry()?;
+        if !justfile.has_recipe(service) {
+            return Err(DispatcherError::ServiceNotFoundError(service.to_string()));
+        }
+        self.start_service(service, options, &justfile)
+    }
+    /// Start service of existing recipe
+    fn start_service(
+        &mut self,
+        service: &str,
+        options: &ServiceOptions,
+        justfile: &Justfile,
+    ) -> Result<Vec<JobId>, DispatcherError> {
+        let wrap = options.wrap.as_deref().map(split_command).transpose()?;
+        let ready_cmd = options
+            .ready_cmd
+            .as_deref()
+            .map(split_command)
+            .transpose()?;
+        let pre_stop = options.pre_stop.as_deref().map(split_command).transpose()?;
+        // Find existing job or add new
+        let job_id = self.find_job(service).unwrap_or_else(|| {
+            let mut job = JobInfo::new_service(service.to_string());
+            job.workdir = justfile.working_directory();
+            self.add_job(job)
+        });
+        if let Some(job) = self.jobs.get_mut(&job_id) {
+            job.env = justfile.recipe_env(service);
+            job.recipe = justfile.recipe_definition(service);
+            job.readiness = justfile.recipe_readiness(service)?;
+            job.log_file = justfile
+                .recipe_log_file(service)
+                .map(|path| job.workdir.as_deref().unwrap_or(Path::new("")).join(path));
+            options.apply_restart(&mut job.restart);
+            if let Some(wrap) = wrap {
+                job.wrap = wrap;
+            }
+            if let Some(log_streams) = options.log_streams {
+                job.log_streams = log_streams;
+            }
+            if options.no_buffer {
+                job.no_buffer = true;
+            }
+            if let Some(ready_cmd) = ready_cmd {
+                job.ready_cmd = ready_cmd;
+            }
+            if let Some(pre_stop) = pre_stop {
+                job.pre_stop = pre_stop;
+            }
+        }
+        // Check for existing process for this service
+        let running = self
+            .procs
+            .lock()
+            .expect("lock")
+            .iter_mut()
+            .any(|child| child.info.job_id == job_id && child.is_running());
+        if running {
+            Ok(vec![])
+        } else {
+            self.spawn_job(job_id, self.runner_ctx.log_lines)?;
+            self.wait_probe(job_id, READINESS_TIMEOUT)?;
+            Ok(vec![job_id])
+        }
+    }
+    /// Restart service with the current recipe of the justfile, if it changed
+    fn reload(&mut self, service: &str) -> Result<(), DispatcherError> {
+        let justfile = Justfile::parse_with_retry()?;
+        let job_id = self
+            .find_job(service)
+            .filter(|_| justfile.has_recipe(service))
+            .ok_or(DispatcherError::ServiceNotFoundError(service.to_string()))?;
+        let Some(job) = self.jobs.get_mut(&job_id) else {
+            return Err(DispatcherError::JobNotFoundError(job_id));
+        };
+        let env = justfile.recipe_env(service);
+        let workdir = justfile.working_directory();
+        let recipe = justfile.recipe_definition(service);
+        if job.env == env && job.workdir == workdir && job.recipe == recipe {
+            info!("Service `{service}` unchanged");
+            return Ok(());
+        }
+        job.env = env;
+        job.workdir = workdir;
+        job.recipe = recipe;
+        self.save_state();
+        info!("Restarting changed service `{service}`");
+        let job = self.spawn_info(job_id)?;
+        restart_procs(&self.procs, &job, &self.runner_ctx)
+    }
+    /// Start service group (all just repipes in group)
+    fn up(
+        &mut self,
+        group: &str,
+        selection: &ServiceSelection,
+        pacing: &StartupPacing,
+        options: &ServiceOptions,
+        wait_timeout: Option<Duration>,
+        mut progress: Option<&mut IpcStream>,
+    ) -> Result<Vec<JobId>, DispatcherError> {
+        let mut job_ids = Vec::new();
+        let justfile = Justfile::parse_with_retry()?;
+        let recipes = select_services(group, justfile.ordered_group_recipes(group)?, selection)?;
+        let StartupPacing {
+            concurrency,
+            stagger,
+        } = pacing;
+        let batch_size = concurrency.map(NonZeroUsize::get).unwrap_or(recipes.len());
+        let mut step = 0;
+        for batch in recipes.chunks(batch_size.max(1)) {
+            let mut batch_ids = Vec::new();
+            for service in batch {
+                if let Some(stagger) = stagger.filter(|_| step > 0) {
+                    thread::sleep(stagger.delay());
+                }
+                step += 1;
+                if let Some(stream) = progress.as_deref_mut() {
+                    let _ = stream.send_message(&Message::Progress {
+                        step,
+                        total: recipes.len(),
+                        detail: format!("starting {service}"),
+                    });
+                }
+                let ids = self.start_service(service, options, &justfile)?;
+                batch_ids.extend(ids);
+            }
+            if concurrency.is_some() {
+                self.wait_started(&batch_ids, None)?;
+                // Dependents in later batches start after readiness checks succeeded
+                if let Some(timeout) = wait_timeout {
+                    self.wait_ready(&batch_ids, timeout)?;
+                }
+            }
+            job_ids.extend(batch_ids);
+        }
+        if let Some(timeout) = wait_timeout {
+            self.wait_started(&job_ids, Some(timeout))?;
+            let mut procs = self.procs.lock().expect("lock");
+            for job_id in &job_ids {
+                let running = procs
+                    .iter_mut()
+                    .any(|child| child.info.job_id == *job_id && child.is_running());
+                if !running {
+                    return Err(DispatcherError::StartupFailedError(*job_id));
+                }
+            }
+            drop(procs);
+            self.wait_ready(&job_ids, timeout)?;
+        }
+        Ok(job_ids)
+    }
+    /// Run readiness commands of jobs until they succeed
+    fn wait_ready(&self, job_ids: &[JobId], timeout: Duration) -> Result<(), DispatcherError> {
+        let start = Instant::now();
+        for job_id in job_ids {
+            let Some(job) = self
+                .jobs
+                .get(job_id)
+                .filter(|job| !job.ready_cmd.is_empty())
+            else {
+                continue;
+            };
+            loop {
+                // Hanging commands are killed when the timeout is reached
+                let remaining = timeout.saturating_sub(start.elapsed());
+                match Runner::check(&job.ready_cmd, Some(remaining), &self.runner_ctx) {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(DispatcherError::CommandTimeoutError(_)) => {
+                        return Err(DispatcherError::NotReadyError(*job_id, timeout));
+                    }
+                    Err(e) => return Err(e),
+                }
+                if start.elapsed() > timeout {
+                    return Err(DispatcherError::NotReadyError(*job_id, timeout));
+                }
+                thread::sleep(READY_CHECK_INTERVAL);
+            }
+            info!("Job {job_id} is ready");
+        }
+        Ok(())
+    }
+    /// Wait until readiness probe of a started service passes
+    fn wait_probe(&self, job_id: JobId, timeout: Duration) -> Result<(), DispatcherError> {
+        let Some(probe) = self
+            .jobs
+            .get(&job_id)
+            .and_then(|job| job.readiness.as_ref())
+        else {
+            return Ok(());
+        };
+        // Output of the latest process
+        let output = self
+            .procs
+            .lock()
+            .expect("lock")
+            .iter()
+            .rev()
+            .find(|child| child.info.job_id == job_id)
+            .map(|child| child.output.clone())
+            .ok_or(DispatcherError::JobNotFoundError(job_id))?;
+        let start = Instant::now();
+        loop {
+            let ready = match probe {
+                Readiness::Tcp(addr) => tcp_connectable(addr),
+                Readiness::Log(pattern) => {
+                    // Pattern is validated when reading the justfile
+                    let regex = Regex::new(pattern).expect("valid regex");
+                    output
+                        .lock()
+                        .is_ok_and(|output| output.lines().any(|line| regex.is_match(&line.line)))
+                }
+            };
+            if ready {
+                info!("Job {job_id} is ready");
+                return Ok(());
+            }
+            let running = self
+                .procs
+                .lock()
+                .expect("lock")
+                .iter_mut()
+                .any(|child| child.info.job_id == job_id && child.is_running());
+            if !running {
+                return Err(DispatcherError::StartupFailedError(job_id));
+            }
+            if start.elapsed() > timeout {
+                return Err(DispatcherError::NotReadyError(job_id, timeout));
+            }
+            thread::sleep(READY_CHECK_INTERVAL);
+        }
+    }
+    /// Wait until processes of jobs are running or terminated
+    fn wait_started(
+        &self,
+        job_ids: &[JobId],
+        timeout: Option<Duration>,
+    ) -> Result<(), DispatcherError> {
+        let start = Instant::now();
+        loop {
+            let starting = self
+                .procs
+                .lock()
+                .expect("lock")
+                .iter_mut()
+                .filter(|child| job_ids.contains(&child.info.job_id))
+                .any(|child| {
+                    matches!(
+                        child.update_proc_state().state,
+                        ProcStatus::Spawned | ProcStatus::Starting
+                    )
+                });
+            if !starting {
+                return Ok(());
+            }
+            if let Some(timeout) = timeout.filter(|timeout| start.elapsed() > *timeout) {
+                return Err(DispatcherError::StartupTimeoutError(timeout));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+    /// Stop service group including dependencies started by `up`.
+    /// Dependents are stopped before the services they depend on.
+    fn down(&mut self, group: &str, selection: &ServiceSelection) -> Result<(), DispatcherError> {
+        let justfile = Justfile::parse_with_retry()?;
+        let ordered = justfile.ordered_group_recipes(group)?;
+        let recipes = select_services(group, ordered.clone(), selection)?;
+        for stage in justfile.dependency_stages(&ordered).iter().rev() {
+            let job_ids: Vec<JobId> = self
+                .jobs
+                .iter()
+                .filter(|(_id, info)| matches!(&info.job_type, JobType::Service(name) if stage.contains(name) && recipes.contains(name)))
+                .map(|(id, _info)| *id)
+                .collect();
+            self.stop_jobs(&job_ids, false, DEFAULT_GRACE_PERIOD)?;
+        }
+        Ok(())
+    }
+    /// Return info about running and finished processes
+    fn ps(
+        &mut self,
+        cpu_interval: Duration,
+        stream: &mut IpcStream,
+    ) -> Result<(), DispatcherError> {
+        let proc_infos = proc_stats(&mut self.system, &self.procs, cpu_interval);
+        stream.send_message(&Message::PsInfo(proc_infos))?;
+        Ok(())
+    }
+    /// Send job info or effective environment of latest job process
+    fn inspect(
+        &mut self,
+        job_id: JobId,
+        env: bool,
+        stream: &mut IpcStream,
+    ) -> Result<(), DispatcherError> {
+        let job = self
+            .jobs
+            .get(&job_id)
+            .ok_or(DispatcherError::JobNotFoundError(job_id))?;
+        let procs = self.procs.lock().expect("lock");
+        let mut job_procs = procs.iter().filter(|child| child.info.job_id == job_id);
+        if env {
+            let child = job_procs
+                .next_back()
+                .ok_or(DispatcherError::JobNotFoundError(job_id))?;
+            let mut vars = child
+                .effective_env
+                .clone()
+                .ok_or(DispatcherError::EnvNotRecordedError)?;
+            // Hide secret values
+            for (key, value) in vars.iter_mut() {
+                if job.secret_env.iter().any(|(secret, _)| secret == key) {
+                    *value = Secret::redacted().0;
+                }
+            }
+            drop(procs);
+            stream.send_message(&Message::Environment(vars))?;
+        } else {
+            let mut output = OutputStats::default();
+            for child in job_procs {
+                if let Ok(buffer) = child.output.lock() {
+                    output += buffer.stats;
+                }
+            }
+            let job = Job {
+                id: job_id,
+                info: job.redacted(),
+                output,
+                next_run: self.next_run(job_id),
+            };
+            drop(procs);
+            stream.send_message(&Message::JobInfo(vec![job]))?;
+        }
+        Ok(())
+    }
+    /// Return final state of the most recently terminated process of a job
+    fn why(&mut self, job_id: JobId, stream: &mut IpcStream) -> Result<(), DispatcherError> {
+        let (state, end) = self
+            .runner_ctx
+            .last_exit
+            .lock()
+            .expect("lock")
+            .get(&job_id)
+            .cloned()
+            .ok_or(DispatcherError::NoExitError(job_id))?;
+        stream.send_message(&Message::ExitReason(ExitReason { job_id, state, end }))?;
+        Ok(())
+    }
+    /// Reassign sequential job ids. Returns changed ids as (old, new).
+    /// Requests are handled one at a time, so only scheduled jobs reference ids concurrently.
+    fn renumber(&mut self) -> Result<Vec<(JobId, JobId)>, DispatcherError> {
+        let mapping: HashMap<JobId, JobId> = self
+            .jobs
+            .keys()
+            .zip(1..)
+            .map(|(old_id, new_id)| (*old_id, new_id))
+            .collect();
+        // Scheduled jobs capture their job ids
+        let scheduled: Vec<JobId> = self.cronjobs.keys().copied().collect();
+        for job_id in &scheduled {
+            self.unschedule_job(*job_id);
+        }
+        self.jobs = std::mem::take(&mut self.jobs)
+            .into_iter()
+            .map(|(job_id, job)| (mapping[&job_id], job))
+            .collect();
+        self.last_job_id = self.jobs.len() as JobId;
+        // Per-job state of the child watcher
+        remap_jobs(&mut self.runner_ctx.runs.lock().expect("lock"), &mapping);
+        remap_jobs(
+            &mut self.runner_ctx.last_exit.lock().expect("lock"),
+            &mapping,
+        );
+        remap_jobs(
+            &mut self.runner_ctx.restarts.lock().expect("lock"),
+            &mapping,
+        );
+        remap_jobs(
+            &mut self.runner_ctx.termination_log.lock().expect("lock"),
+            &mapping,
+        );
+        for child in self.procs.lock().expect("lock").iter_mut() {
+            if let Some(new_id) = mapping.get(&child.info.job_id) {
+                child.info.job_id = *new_id;
+                if let Ok(mut output) = child.output.lock() {
+                    output.set_job_id(*new_id);
+                }
+            }
+        }
+        for job_id in scheduled.iter().filter_map(|job_id| mapping.get(job_id)) {
+            self.schedule_job(*job_id)?;
+        }
+        let mut renumbered: Vec<(JobId, JobId)> = mapping
+            .into_iter()
+            .filter(|(old_id, new_id)| old_id != new_id)
+            .collect();
+        renumbered.sort();
+        for (old_id, new_id) in &renumbered {
+            info!("Job {old_id} renumbered to {new_id}");
+        }
+        self.save_state();
+        Ok(renumbered)
+    }
+    /// Return info about jobs
+    fn jobs(&mut self, stream: &mut IpcStream) -> Result<(), DispatcherError> {
+        let mut job_infos = Vec::new();
+        let procs = self.procs.lock().expect("lock");
+        for (id, info) in self.jobs.iter().rev() {
+            let mut output = OutputStats::default();
+            for child in procs.iter().filter(|child| child.info.job_id == *id) {
+                if let Ok(buffer) = child.output.lock() {
+                    output += buffer.stats;
+                }
+            }
+            job_infos.push(Job {
+                id: *id,
+                info: info.redacted(),
+                output,
+                next_run: self.next_run(*id),
+            });
+        }
+        drop(procs);
+        stream.send_message(&Message::JobInfo(job_infos))?;
+        Ok(())
+    }
+    /// Return status of background process
+    fn status(&mut self, stream: &mut IpcStream) -> Result<(), DispatcherError> {
+        let running = self
+            .procs
+            .lock()
+            .expect("lock")
+            .iter_mut()
+            .filter_map(|child| child.is_running().then_some(()))
+            .count();
+        let status = DaemonStatus {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started: self.started,
+            jobs: self.jobs.len(),
+            running,
+            pending_exits: self.runner_ctx.pending_exits.load(Ordering::Relaxed),
+        };
+        stream.send_message(&Message::DaemonStatus(status))?;
+        Ok(())
+    }
+    /// Return log lines
+    fn log(
+        &mut self,
+        selection: LogSelection,
+        follow: bool,
+        status_interval: Option<Duration>,
+        tail: Option<usize>,
+        since: Option<DateTime<Local>>,
+        stream: &mut IpcStream,
+    ) -> Result<(), DispatcherError> {
+        let LogSelection {
+            job_or_service,
+            group,
+            streams,
+        } = selection;
+        let mut job_id_filter = None;
+        if let Some(job_or_service) = job_or_service {
+            job_id_filter = Some(vec![self.resolve_job(&job_or_service)?]);
+        } else if let Some(group) = group {
+            let justfile = Justfile::parse_with_retry()?;
+            let job_ids: Vec<JobId> = justfile
+                .group_recipes(&group)
+                .iter()
+                .filter_map(|service| self.find_job(service))
+                .collect();
+            if job_ids.is_empty() {
+                return Err(DispatcherError::GroupJobsNotFoundError(group));
+            }
+            job_id_filter = Some(job_ids);
+        }
+
+        let mut last_seen_ts: HashMap<Pid, DateTime<Local>> = HashMap::new();
+        if let Some(since) = since {
+            // Lines of processes spawned later are newer anyway
+            for child in self.procs.lock().expect("lock").iter() {
+                last_seen_ts.insert(child.proc.id(), since);
+            }
+        }
+        if let Some(n) = tail {
+            // Skip all but the last n buffered lines of each process
+            for child in self.procs.lock().expect("lock").iter() {
+                if let Some(ts) = child.output.lock().ok().and_then(|o| o.tail_start(n)) {
+                    last_seen_ts.insert(child.proc.id(), ts);
+                }
+            }
+        }
+        let mut last_status: Option<Instant> = None;
+        'logwait: loop {
+            if let Some(interval) = status_interval {
+                if last_status.is_none_or(|ts| ts.elapsed() >= interval) {
+                    let proc_infos = self.latest_proc_infos(job_id_filter.as_deref());
+                    stream.send_message(&Message::PsInfo(proc_infos))?;
+                    last_status = Some(Instant::now());
+                }
+            }
+            // Collect log entries from child proceses
+            let mut log_lines = self.collect_log_lines(&mut last_seen_ts, job_id_filter.as_deref());
+            if streams != LogStreams::Both {
+                log_lines.retain(|entry| {
+                    entry.is_termination_marker()
+                        || (entry.is_stderr && streams.stderr())
+                        || (!entry.is_stderr && streams.stdout())
+                });
+            }
+
+            if log_lines.is_empty() {
+                // Exit when client is disconnected
+                stream.alive()?;
+            } else {
+                for entry in log_lines {
+                    if stream.send_message(&Message::LogLine(entry)).is_err() {
                         info!("Aborting log command (stream error)");
                         break 'logwait;
                     }
                 }
             }
+            if !follow {
+                break;
+            }
             // Wait for new output
             thread::sleep(Duration::from_millis(100));
         }
         Ok(())
     }
+    /// Process infos of the latest process of each job
+    fn latest_proc_infos(&self, job_id_filter: Option<&[JobId]>) -> Vec<ProcInfo> {
+        let mut proc_infos: Vec<ProcInfo> = Vec::new();
+        for child in self.procs.lock().expect("lock").iter_mut().rev() {
+            let job_id = child.info.job_id;
+            if job_id_filter.is_some_and(|job_ids| !job_ids.contains(&job_id))
+                || proc_infos.iter().any(|info| info.job_id == job_id)
+            {
+                continue;
+            }
+            proc_infos.push(child.update_proc_state().clone());
+        }
+        proc_infos.sort_by_key(|info| info.job_id);
+        proc_infos
+    }
+    /// Collect log lines newer than the last seen timestamp of each process
+    fn collect_log_lines(
+        &self,
+        last_seen_ts: &mut HashMap<Pid, DateTime<Local>>,
+        job_id_filter: Option<&[JobId]>,
+    ) -> Vec<LogLine> {
+        let mut log_lines = Vec::new();
+        for child in self.procs.lock().expect("lock").iter_mut() {
+            if let Ok(output) = child.output.lock() {
+                let last_seen = last_seen_ts
+                    .entry(child.proc.id())
+                    .or_insert(Local.timestamp_millis_opt(0).single().expect("ts"));
+                for entry in output.lines_since(last_seen) {
+                    if let Some(job_ids) = job_id_filter {
+                        if !job_ids.contains(&entry.job_id) {
+                            continue;
+                        }
+                    }
+                    log_lines.push(entry.clone());
+                }
+            }
+        }
+        log_lines.sort_by_key(|entry| entry.ts);
+        log_lines
+    }
+    /// Complete stdout output of terminated process
+    fn captured_output(&self, pid: Pid) -> String {
+        let procs = self.procs.lock().expect("lock");
+        let Some(output) = procs
+            .iter()
+            .find(|child| child.info.pid == pid)
+            .and_then(|child| child.output.lock().ok())
+        else {
+            return String::new();
+        };
+        let mut lines: Vec<&LogLine> = output.lines().filter(|entry| !entry.is_stderr).collect();
+        if lines
+            .last()
+            .is_some_and(|entry| entry.is_termination_marker())
+        {
+            lines.pop();
+        }
+        lines
+            .iter()
+            .map(|entry| format!("{}\n", entry.line))
+            .collect()
+    }
+    /// Wait for termination of the job process and return its exit code
+    fn wait(
+        &mut self,
+        job_id: JobId,
+        mode: WaitMode,
+        stream: &mut IpcStream,
+    ) -> Result<i32, DispatcherError> {
+        let pid = self
+            .procs
+            .lock()
+            .expect("lock")
+            .iter()
+            .rev()
+            .find(|child| child.info.job_id == job_id)
+            .map(|child| child.info.pid)
+            .ok_or(DispatcherError::JobNotFoundError(job_id))?;
+        let mut last_seen_ts: HashMap<Pid, DateTime<Local>> = HashMap::new();
+        loop {
+            // Process is terminated, when the watcher has set its end time
+            let exit_code = self
+                .procs
+                .lock()
+                .expect("lock")
+                .iter_mut()
+                .find(|child| child.info.pid == pid && child.info.end.is_some())
+                .map(|child| child.exit_code().unwrap_or(1));
+            let log_lines = if mode == (WaitMode::Output { quiet: false }) {
+                self.collect_log_lines(&mut last_seen_ts, Some(&[job_id]))
+                    .into_iter()
+                    .filter(|entry| entry.pid == pid)
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            if log_lines.is_empty() {
+                // Stop waiting when client is disconnected. The job keeps running.
+                stream.alive()?;
+            }
+            for entry in log_lines {
+                stream.send_message(&Message::LogLine(entry))?;
+            }
+            if let Some(exit_code) = exit_code {
+                if mode == WaitMode::Capture {
+                    stream.send_message(&Message::CapturedOutput(self.captured_output(pid)))?;
+                }
+                return Ok(exit_code);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Parse cron expression, accepting standard 5-field expressions without seconds
+pub fn parse_schedule(cron: &str) -> Result<cron::Schedule, cron::error::Error> {
+    if cron.split_whitespace().count() == 5 {
+        format!("0 {cron}").parse()
+    } else {
+        cron.parse()
+    }
+}
+
+/// Replace job ids of map keys, dropping entries of unknown jobs
+fn remap_jobs<V>(map: &mut HashMap<JobId, V>, mapping: &HashMap<JobId, JobId>) {
+    *map = map
+        .drain()
+        .filter_map(|(job_id, value)| Some((*mapping.get(&job_id)?, value)))
+        .collect();
+}
+
+/// Split command line into arguments, respecting shell quoting (e.g. `sh -c 'curl ...'`)
+fn split_command(command: &str) -> Result<Vec<String>, DispatcherError> {
+    shell_words::split(command)
+        .map_err(|e| DispatcherError::InvalidCommandError(command.to_string(), e.to_string()))
+}
+
+/// Filter services of a group with `--only` or `--except` selection
+fn select_services(
+    group: &str,
+    recipes: Vec<String>,
+    selection: &ServiceSelection,
+) -> Result<Vec<String>, DispatcherError> {
+    let ServiceSelection { only, except } = selection;
+    if let Some(service) = only
+        .iter()
+        .chain(except)
+        .find(|service| !recipes.contains(service))
+    {
+        return Err(DispatcherError::ServiceNotInGroupError(
+            service.clone(),
+            group.to_string(),
+        ));
+    }
+    Ok(recipes
+        .into_iter()
+        .filter(|service| only.is_empty() || only.contains(service))
+        .filter(|service| !except.contains(service))
+        .collect())
+}
+
+/// Update resource usage of all processes.
+/// Blocks for `cpu_interval`, which should be at least the minimal CPU update interval of sysinfo.
+/// CPU usage is not measured with a zero interval.
+pub fn proc_stats(
+    system: &mut System,
+    procs: &Mutex<Vec<Runner>>,
+    cpu_interval: Duration,
+) -> Vec<ProcInfo> {
+    // Update system info
+    // For accurate CPU usage, a process needs to be refreshed twice
+    // https://docs.rs/sysinfo/latest/i686-pc-windows-msvc/sysinfo/struct.Process.html#method.cpu_usage
+    let ts = Local::now();
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        ProcessRefreshKind::new().with_cpu(),
+    );
+    // Collect pids and descendant pids
+    let pids: Vec<sysinfo::Pid> = procs
+        .lock()
+        .expect("lock")
+        .iter()
+        .flat_map(|proc| {
+            let parent_pid = sysinfo::Pid::from(proc.info.pid as usize);
+            descendant_processes(system, parent_pid)
+                .into_iter()
+                .chain([parent_pid])
+        })
+        .collect();
+    std::thread::sleep(cpu_interval);
+    // Avoid division by zero for rates without sampling interval
+    let duration = (Local::now() - ts).num_milliseconds().max(1);
+    fn per_second(value: u64, ms: i64) -> u64 {
+        (value as f64 * 1000.0 / ms as f64) as u64
+    }
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&pids),
+        true,
+        ProcessRefreshKind::new()
+            .with_cpu()
+            .with_disk_usage()
+            .with_memory(),
+    );
+
+    let mut proc_infos = Vec::new();
+    for child in &mut procs.lock().expect("lock").iter_mut().rev() {
+        let parent_pid = sysinfo::Pid::from(child.info.pid as usize);
+        // CPU usage has to be measured from the process doing the work,
+        // e.g. the shebang interpreter of a just recipe
+        let main_pid = main_process(system, parent_pid);
+        if let Some(process) = system.process(main_pid) {
+            child.info.cpu = process.cpu_usage();
+            child.info.memory = process.memory();
+            child.info.virtual_memory = process.virtual_memory();
+            let disk = process.disk_usage();
+            child.info.total_written_bytes = disk.total_written_bytes;
+            child.info.written_bytes = per_second(disk.written_bytes, duration);
+            child.info.total_read_bytes = disk.total_read_bytes;
+            child.info.read_bytes = per_second(disk.read_bytes, duration);
+        } else {
+            child.info.cpu = 0.0;
+            child.info.memory = 0;
+            child.info.virtual_memory = 0;
+            child.info.written_bytes = 0;
+            child.info.read_bytes = 0;
+        }
+        let info = child.update_proc_state();
+        proc_infos.push(info.clone());
+    }
+    proc_infos
+}
+
+/// Terminate running processes of a job and spawn a new one.
+/// Jobs without running process are not restarted.
+fn restart_procs(
+    procs: &Arc<Mutex<Vec<Runner>>>,
+    job: &JobSpawnInfo,
+    runner_ctx: &RunnerContext,
+) -> Result<(), DispatcherError> {
+    let job_id = job.job_id;
+    let mut terminated = false;
+    let mut max_log_lines = runner_ctx.log_lines;
+    for child in procs
+        .lock()
+        .expect("lock")
+        .iter_mut()
+        .filter(|child| child.info.job_id == job_id)
+    {
+        if child.is_running() {
+            if let Ok(output) = child.output.lock() {
+                max_log_lines = output.max_len();
+            }
+            child.user_terminated = true;
+            runner_ctx.audit(AuditEvent::Restart, &child.info, None);
+            child.terminate().map_err(DispatcherError::KillError)?;
+            terminated = true;
+        }
+    }
+    if !terminated {
+        return Ok(());
+    }
+    // Wait for termination before spawning the new process
+    wait_terminated(procs, job_id);
+    let child = Runner::spawn(
+        job_id,
+        &job.args,
+        &job.env,
+        job.workdir.as_deref(),
+        job.restart_info.clone(),
+        OutputOptions {
+            max_lines: max_log_lines,
+            streams: job.log_streams,
+            log_file: job.log_file.clone(),
+        },
+        runner_ctx,
+    )?;
+    procs.lock().expect("lock").push(child);
+    Ok(())
+}
+
+/// Wait up to 5s until no process of a job is running
+fn wait_terminated(procs: &Mutex<Vec<Runner>>, job_id: JobId) {
+    for _ in 0..50 {
+        let running = procs
+            .lock()
+            .expect("lock")
+            .iter_mut()
+            .any(|child| child.info.job_id == job_id && child.is_running());
+        if !running {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
 }
 
 fn cron_scheduler(scheduler: Arc<Mutex<JobScheduler<'static>>>) {
@@ -502,59 +2060,453 @@ fn cron_scheduler(scheduler: Arc<Mutex<JobScheduler<'static>>>) {
     }
 }
 
-// sender: Sender channel for Runner threads
+/// Delay between failed readiness checks
+const READY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Persisted jobs of the background process
+#[derive(Serialize, Deserialize)]
+struct DispatcherState {
+    last_job_id: JobId,
+    jobs: BTreeMap<JobId, JobInfo>,
+}
+
+/// Base directory for state files of the current user
+#[cfg(unix)]
+fn user_state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".local").join("state")))
+}
+
+/// Base directory for state files of the current user
+#[cfg(not(unix))]
+fn user_state_dir() -> Option<PathBuf> {
+    env::var_os("LOCALAPPDATA").map(PathBuf::from)
+}
+
+/// State file in a directory only accessible by the current user.
+/// The directory is created if missing and rejected if other users could modify its content.
+fn state_file_path() -> Result<PathBuf, io::Error> {
+    let dir = user_state_dir()
+        .ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no home directory for state file",
+        ))?
+        .join("shell-compose");
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    if let Err(e) = builder.create(&dir) {
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            return Err(e);
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let meta = fs::symlink_metadata(&dir)?;
+        // SAFETY: getuid has no preconditions and always succeeds
+        let own_uid = unsafe { libc::getuid() };
+        if !meta.is_dir() || meta.uid() != own_uid || meta.mode() & 0o077 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not a private directory", dir.display()),
+            ));
+        }
+    }
+    Ok(dir.join("state.json"))
+}
+
+/// Write state into a temporary file, which replaces the state file when complete
+fn write_state_file(path: &Path, state: &DispatcherState) -> Result<(), io::Error> {
+    let tmp_path = path.with_extension(format!("json.{:016x}.tmp", fastrand::u64(..)));
+    let mut options = fs::OpenOptions::new();
+    // Never reuse an existing file, which could be owned by someone else
+    options.write(true).create_new(true);
+    // State contains secret environment values
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp_path)?;
+    let res = serde_json::to_writer(&mut file, state)
+        .map_err(io::Error::from)
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+/// Maximal waiting time for readiness probes of starting services
+const READINESS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Whether a TCP connection to `addr` (`HOST:PORT`) can be established
+fn tcp_connectable(addr: &str) -> bool {
+    let Ok(addrs) = addr.to_socket_addrs() else {
+        return false;
+    };
+    addrs
+        .into_iter()
+        .any(|addr| TcpStream::connect_timeout(&addr, READY_CHECK_INTERVAL).is_ok())
+}
+
+/// Maximal duration of pre-stop commands
+const PRE_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of termination messages per job logged within the window
+const TERMINATION_LOG_BURST: usize = 3;
+/// Window for rate limiting termination messages
+const TERMINATION_LOG_WINDOW: TimeDelta = TimeDelta::minutes(1);
+
+/// Rate limiting of termination messages of a job
+#[derive(Default)]
+pub(crate) struct TerminationLog {
+    /// Termination times within window
+    times: VecDeque<DateTime<Local>>,
+    last_summary: Option<DateTime<Local>>,
+}
+
+enum TerminationLogAction {
+    Log,
+    /// Log number of terminations within window
+    Summary(usize),
+    Suppress,
+}
+
+impl TerminationLog {
+    fn record(&mut self, ts: DateTime<Local>) -> TerminationLogAction {
+        while self
+            .times
+            .front()
+            .is_some_and(|t| ts - *t > TERMINATION_LOG_WINDOW)
+        {
+            self.times.pop_front();
+        }
+        self.times.push_back(ts);
+        if self.times.len() <= TERMINATION_LOG_BURST {
+            self.last_summary = None;
+            TerminationLogAction::Log
+        } else if self
+            .last_summary
+            .is_none_or(|last| ts - last >= TERMINATION_LOG_WINDOW)
+        {
+            self.last_summary = Some(ts);
+            TerminationLogAction::Summary(self.times.len())
+        } else {
+            TerminationLogAction::Suppress
+        }
+    }
+}
+
+// runner_ctx: Context with sender channel for Runner threads
 // recv: Watcher receiver channel
+// no_restart: Never respawn processes, regardless of their restart policy
 fn child_watcher(
     procs: Arc<Mutex<Vec<Runner>>>,
-    sender: mpsc::Sender<Pid>,
+    runner_ctx: RunnerContext,
     recv: mpsc::Receiver<Pid>,
+    no_restart: bool,
 ) {
     loop {
-        // PID of terminated process sent from output_listener
-        let pid = recv.recv().expect("recv");
+        // PIDs of terminated processes sent from output_listener, processed in batches
+        let pids: Vec<Pid> = [recv.recv().expect("recv")]
+            .into_iter()
+            .chain(recv.try_iter())
+            .collect();
+        runner_ctx
+            .pending_exits
+            .fetch_sub(pids.len(), Ordering::Relaxed);
         let ts = Local::now();
-        let mut respawn_child = None;
-        if let Some(child) = procs
-            .lock()
-            .expect("lock")
-            .iter_mut()
-            .find(|p| p.info.pid == pid)
-        {
+        let mut respawn_children = Vec::new();
+        let mut procs_guard = procs.lock().expect("lock");
+        let mut termination_log = runner_ctx.termination_log.lock().expect("lock");
+        let mut restarts = runner_ctx.restarts.lock().expect("lock");
+        for pid in pids {
+            let Some(child) = procs_guard.iter_mut().find(|p| p.info.pid == pid) else {
+                info!(target: &format!("{pid}"), "(Unknown) process terminated");
+                continue;
+            };
             // https://doc.rust-lang.org/std/process/struct.Child.html#warning
             let exit_code = child.proc.wait().ok().and_then(|st| st.code());
             let _ = child.update_proc_state();
             child.info.end = Some(ts);
-            if let Some(code) = exit_code {
-                info!(target: &format!("{pid}"), "Process terminated with exit code {code}");
-            } else {
-                info!(target: &format!("{pid}"), "Process terminated");
+            child.push_terminated(exit_code);
+            runner_ctx.audit(AuditEvent::Exit, &child.info, exit_code);
+            let job_id = child.info.job_id;
+            runner_ctx
+                .last_exit
+                .lock()
+                .expect("lock")
+                .insert(job_id, (child.info.state.clone(), ts));
+            match termination_log.entry(job_id).or_default().record(ts) {
+                TerminationLogAction::Log => {
+                    if let Some(code) = exit_code {
+                        info!(target: &format!("{pid}"), "Process terminated with exit code {code}");
+                    } else {
+                        info!(target: &format!("{pid}"), "Process terminated");
+                    }
+                }
+                TerminationLogAction::Summary(count) => {
+                    info!(
+                        "Job {job_id} terminated {count} times in the last minute - suppressing further messages"
+                    );
+                }
+                TerminationLogAction::Suppress => {}
             }
-            let respawn = !child.user_terminated
+            let respawn = !no_restart
+                && !child.user_terminated
+                && !child.paused
                 && match child.restart_info.policy {
                     Restart::Always => true,
                     Restart::OnFailure => {
                         matches!(child.info.state, ProcStatus::ExitErr(code) if code > 0)
                     }
+                    Restart::OnSuccess => matches!(child.info.state, ProcStatus::ExitOk),
                     Restart::Never => false,
                 };
-            if respawn {
-                respawn_child = Some((child.info.clone(), child.restart_info.clone()));
+            if !respawn {
+                continue;
             }
-        } else {
-            info!(target: &format!("{pid}"), "(Unknown) process terminated");
-        }
-        if let Some((child_info, restart_info)) = respawn_child {
-            thread::sleep(Duration::from_millis(restart_info.wait_time));
-            let result = Runner::spawn(
-                child_info.job_id,
-                &child_info.cmd_args,
-                restart_info,
-                sender.clone(),
-            );
-            match result {
-                Ok(child) => procs.lock().expect("lock").push(child),
-                Err(e) => error!("Error trying to respawn failed process: {e}"),
+            if child.held {
+                info!("Job {job_id} is held - not restarting");
+                continue;
+            }
+            let window = child.restart_info.backoff.reset_after;
+            let times = restarts.entry(job_id).or_default();
+            // Successful runs (e.g. polling loops) are no crashes
+            if matches!(child.info.state, ProcStatus::ExitOk) {
+                times.clear();
             }
+            while times
+                .front()
+                .is_some_and(|t| (ts - *t).to_std().unwrap_or_default() > window)
+            {
+                times.pop_front();
+            }
+            if times.len() >= child.restart_info.max_restarts {
+                error!(
+                    "Job {job_id} restarted {} times within {} - giving up",
+                    times.len(),
+                    humantime::format_duration(window)
+                );
+                child.info.state = ProcStatus::CrashLooping;
+                times.clear();
+                continue;
+            }
+            let delay = child.restart_info.delay(times.len());
+            if matches!(child.info.state, ProcStatus::ExitErr(_)) {
+                times.push_back(ts);
+            }
+            // Keep log buffer size changed with `set` command
+            let mut output_options = child.output_options.clone();
+            if let Ok(output) = child.output.lock() {
+                output_options.max_lines = output.max_len();
+            }
+            respawn_children.push((
+                child.info.clone(),
+                child.env.clone(),
+                child.cwd.clone(),
+                child.restart_info.clone(),
+                output_options,
+                delay,
+            ));
+        }
+        drop(restarts);
+        drop(termination_log);
+        drop(procs_guard);
+        for (child_info, env, cwd, restart_info, output_options, delay) in respawn_children {
+            let procs = procs.clone();
+            let runner_ctx = runner_ctx.clone();
+            // Wait in a separate thread, to keep handling terminations during backoff
+            thread::spawn(move || {
+                thread::sleep(delay);
+                let mut procs = procs.lock().expect("lock");
+                // Job may have been stopped or renumbered in the meantime
+                let Some(prev) = procs.iter().find(|p| p.info.pid == child_info.pid) else {
+                    return;
+                };
+                if prev.user_terminated || prev.held {
+                    return;
+                }
+                runner_ctx.audit(AuditEvent::Restart, &prev.info, None);
+                let result = Runner::spawn(
+                    prev.info.job_id,
+                    &child_info.cmd_args,
+                    &env,
+                    cwd.as_deref(),
+                    restart_info,
+                    output_options,
+                    &runner_ctx,
+                );
+                match result {
+                    Ok(child) => procs.push(child),
+                    Err(e) => error!("Error trying to respawn failed process: {e}"),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_without_seconds() {
+        let schedule = parse_schedule("*/15 * * * *").unwrap();
+        let start = Local.with_ymd_and_hms(2024, 1, 1, 10, 7, 30).unwrap();
+        let next: Vec<_> = schedule.after(&start).take(2).collect();
+        assert_eq!(
+            next,
+            [
+                Local.with_ymd_and_hms(2024, 1, 1, 10, 15, 0).unwrap(),
+                Local.with_ymd_and_hms(2024, 1, 1, 10, 30, 0).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn schedule_with_seconds() {
+        let schedule = parse_schedule("30 0 12 * * *").unwrap();
+        let start = Local.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            schedule.after(&start).next(),
+            Some(Local.with_ymd_and_hms(2024, 1, 1, 12, 0, 30).unwrap())
+        );
+        // Optional year field
+        assert!(parse_schedule("0 0 12 1 1 * 2030").is_ok());
+        // Surrounding and repeated whitespace in 5-field expressions
+        assert!(parse_schedule("  0  12 * * * ").is_ok());
+    }
+
+    #[test]
+    fn invalid_schedule() {
+        assert!(parse_schedule("").is_err());
+        assert!(parse_schedule("* * *").is_err());
+        assert!(parse_schedule("61 * * * *").is_err());
+        assert!(parse_schedule("every minute").is_err());
+    }
+
+    #[test]
+    fn termination_log_rate_limit() {
+        let mut log = TerminationLog::default();
+        let start = Local.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+        let at = |secs| start + TimeDelta::seconds(secs);
+        for secs in 0..TERMINATION_LOG_BURST as i64 {
+            assert!(matches!(log.record(at(secs)), TerminationLogAction::Log));
         }
+        assert!(matches!(
+            log.record(at(10)),
+            TerminationLogAction::Summary(4)
+        ));
+        assert!(matches!(log.record(at(20)), TerminationLogAction::Suppress));
+        assert!(matches!(log.record(at(30)), TerminationLogAction::Suppress));
+        // Next summary one window after the previous one
+        assert!(matches!(
+            log.record(at(70)),
+            TerminationLogAction::Summary(4)
+        ));
+        // Logging resumes when terminations within the window drop to the burst size
+        assert!(matches!(log.record(at(200)), TerminationLogAction::Log));
+        assert!(matches!(log.record(at(201)), TerminationLogAction::Log));
+        assert!(matches!(log.record(at(202)), TerminationLogAction::Log));
+        assert!(matches!(
+            log.record(at(203)),
+            TerminationLogAction::Summary(4)
+        ));
+    }
+
+    #[test]
+    fn restart_backoff() {
+        let info = RestartInfo {
+            wait_time: 100,
+            ..Default::default()
+        };
+        assert_eq!(info.delay(0), Duration::from_millis(100));
+        assert_eq!(info.delay(1), Duration::from_millis(200));
+        assert_eq!(info.delay(3), Duration::from_millis(800));
+        // Limited by backoff maximum
+        assert_eq!(info.delay(10), Duration::from_secs(30));
+        // Exponent is clamped, so that large restart counts don't overflow
+        assert_eq!(info.delay(64), Duration::from_secs(30));
+        assert_eq!(info.delay(usize::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn restart_backoff_config() {
+        let constant = RestartInfo {
+            wait_time: 500,
+            backoff: BackoffConfig {
+                multiplier: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(constant.delay(0), Duration::from_millis(500));
+        assert_eq!(constant.delay(usize::MAX), Duration::from_millis(500));
+        let immediate = RestartInfo {
+            wait_time: 0,
+            ..Default::default()
+        };
+        assert_eq!(immediate.delay(5), Duration::ZERO);
+        let huge = RestartInfo {
+            wait_time: u64::MAX,
+            backoff: BackoffConfig {
+                max: Duration::MAX,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(huge.delay(usize::MAX) >= Duration::from_millis(u64::MAX / 2));
+    }
+
+    #[test]
+    fn command_quoting() {
+        assert_eq!(
+            split_command("sh -c 'curl -fs localhost/health'").unwrap(),
+            ["sh", "-c", "curl -fs localhost/health"]
+        );
+        assert_eq!(split_command("time  -v").unwrap(), ["time", "-v"]);
+        assert_eq!(
+            split_command(r#"pg_isready -h "my host" \-q"#).unwrap(),
+            ["pg_isready", "-h", "my host", "-q"]
+        );
+        assert!(split_command("").unwrap().is_empty());
+        assert!(matches!(
+            split_command("sh -c 'unterminated"),
+            Err(DispatcherError::InvalidCommandError(_, _))
+        ));
+    }
+
+    #[test]
+    fn run_job_info() {
+        use clap::{FromArgMatches, Subcommand};
+        let matches = ExecCommand::augment_subcommands(clap::Command::new("shell-compose"))
+            .try_get_matches_from([
+                "shell-compose",
+                "run",
+                "--shell",
+                "--no-buffer",
+                "--restart",
+                "on-failure",
+                "--env",
+                "LEVEL=debug",
+                "--",
+                "ls",
+                "|",
+                "wc",
+            ])
+            .unwrap();
+        let cmd = ExecCommand::from_arg_matches(&matches).unwrap();
+        let job_info = JobInfo::new_run_job(&cmd).unwrap();
+        assert_eq!(job_info.args.last().unwrap(), "ls | wc");
+        assert_eq!(job_info.env, [("LEVEL".to_string(), "debug".to_string())]);
+        assert!(job_info.no_buffer);
+        assert!(matches!(job_info.restart.policy, Restart::OnFailure));
+        assert!(JobInfo::new_run_job(&ExecCommand::Trigger { job_id: 1 }).is_none());
     }
 }
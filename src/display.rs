@@ -1,4 +1,4 @@
-use crate::{Job, JobType, ProcInfo, ProcStatus};
+use crate::{DaemonStatus, ExitReason, Job, JobColumn, JobType, ProcInfo, ProcStatus, Restart};
 use anstyle_query::{term_supports_ansi_color, truecolor};
 use bytesize::ByteSize;
 use chrono::Local;
@@ -7,7 +7,13 @@ use env_logger::{
     fmt::style::{AnsiColor, Color, RgbColor, Style},
     Env,
 };
+use log::warn;
+use std::collections::BTreeMap;
 use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use std::{env, fs};
+use terminal_size::{terminal_size, Width};
 
 pub fn init_cli_logger() {
     let color = Formatter::default().log_color_app();
@@ -83,28 +89,88 @@ const ERR_PALETTE: [Style; 20] = [
 
 const UNSTYLED: Style = Style::new();
 
+/// Environment variable with path of a palette file
+const PALETTE_ENV_VAR: &str = "SHELL_COMPOSE_PALETTE";
+
 pub struct Formatter {
     supports_truecolor: bool,
     supports_ansi_color: bool,
+    palette: Vec<Style>,
+    err_palette: Vec<Style>,
 }
 
 impl Default for Formatter {
     fn default() -> Self {
+        let mut palette = PALETTE.to_vec();
+        let mut err_palette = ERR_PALETTE.to_vec();
+        if let Some(path) = env::var_os(PALETTE_ENV_VAR) {
+            match read_palette(Path::new(&path)) {
+                Ok((colors, err_colors)) => {
+                    if !colors.is_empty() {
+                        palette = colors;
+                    }
+                    if !err_colors.is_empty() {
+                        err_palette = err_colors;
+                    }
+                }
+                Err(e) => {
+                    warn!(target: "dispatcher", "Ignoring palette file {}: {e}", path.to_string_lossy())
+                }
+            }
+        }
         Formatter {
             supports_truecolor: truecolor(),
             supports_ansi_color: term_supports_ansi_color(),
+            palette,
+            err_palette,
+        }
+    }
+}
+
+/// Read palette file with one hex color (`#RRGGBB`) per line.
+/// Colors for stderr output are prefixed with `stderr`, e.g. `stderr #ede342`.
+fn read_palette(path: &Path) -> Result<(Vec<Style>, Vec<Style>), String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut colors = Vec::new();
+    let mut err_colors = Vec::new();
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let (hex, is_stderr) = match line.strip_prefix("stderr") {
+            Some(hex) => (hex.trim_start(), true),
+            None => (line, false),
+        };
+        let color = parse_hex_color(hex).ok_or(format!("invalid color `{line}`"))?;
+        let style = Style::new().fg_color(Some(Color::Rgb(color)));
+        if is_stderr {
+            err_colors.push(style);
+        } else {
+            colors.push(style);
         }
     }
+    Ok((colors, err_colors))
+}
+
+fn parse_hex_color(hex: &str) -> Option<RgbColor> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 {
+        return None;
+    }
+    let component = |idx: usize| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok();
+    Some(RgbColor(component(0)?, component(2)?, component(4)?))
 }
 
 impl Formatter {
-    pub fn log_color_proc(&self, idx: usize, err: bool) -> &'static Style {
+    pub fn log_color_proc(&self, idx: usize, err: bool) -> &Style {
         if self.supports_truecolor {
-            if err {
-                &ERR_PALETTE[idx % 20]
+            let palette = if err {
+                &self.err_palette
             } else {
-                &PALETTE[idx % 20]
-            }
+                &self.palette
+            };
+            &palette[idx % palette.len()]
         } else {
             &UNSTYLED
         }
@@ -134,71 +200,293 @@ fn clip_str(text: &str, max_len: usize) -> String {
     }
 }
 
-pub fn proc_info_table(proc_infos: &[ProcInfo]) {
+/// Table width used when stdout is not a terminal
+const DEFAULT_TABLE_WIDTH: u16 = 120;
+
+fn table_width() -> u16 {
+    terminal_size()
+        .map(|(Width(w), _)| w)
+        .unwrap_or(DEFAULT_TABLE_WIDTH)
+}
+
+/// Width of a table with unwrapped cells, including borders and padding
+fn natural_width(header: &[&str], rows: &[Vec<String>], hidden: &[usize]) -> usize {
+    let columns = (0..header.len()).filter(|idx| !hidden.contains(idx));
+    let content: usize = columns
+        .map(|idx| {
+            rows.iter()
+                .map(|row| row[idx].chars().count())
+                .chain([header[idx].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .map(|width| width + 3)
+        .sum();
+    content + 1
+}
+
+fn proc_status(state: &ProcStatus) -> String {
+    match state {
+        ProcStatus::ExitOk => "Success".to_string(),
+        ProcStatus::ExitErr(code) => format!("Error {code}"),
+        ProcStatus::Unknown(err) => clip_str(err, 20),
+        st => format!("{st:?}"),
+    }
+}
+
+/// Single line with status of processes
+pub fn proc_status_line(proc_infos: &[ProcInfo]) -> String {
+    proc_infos
+        .iter()
+        .map(|info| {
+            let name = info.cmd_args.last().map(String::as_str).unwrap_or("");
+            format!("[{}] {name}: {}", info.job_id, proc_status(&info.state))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+const PROC_INFO_HEADER: [&str; 13] = [
+    "Job", "PID", "Status", "Command", "Start", "End", "Cpu", "Mem", "Virt", "Write", "Total",
+    "Read", "Total",
+];
+
+/// Cells of a `ps` row with timestamps formatted with `ts_format` and command clipped to `clip`
+fn proc_info_row(info: &ProcInfo, ts_format: &str, clip: Option<usize>) -> Vec<String> {
+    let status = proc_status(&info.state);
+    let mut command = info.cmd_args.join(" ");
+    if info.manual {
+        command.insert_str(0, "[manual] ");
+    }
+    if let Some(max_len) = clip {
+        command = clip_str(&command, max_len);
+    }
+    let end = if let Some(ts) = info.end {
+        format!("{}", ts.format(ts_format))
+    } else {
+        String::new()
+    };
+    vec![
+        format!("{}", info.job_id),
+        format!("{}", info.pid),
+        status,
+        command,
+        format!("{}", info.start.format(ts_format)),
+        end,
+        format!("{:.1}%", info.cpu),
+        format!("{}", ByteSize(info.memory)),
+        format!("{}", ByteSize(info.virtual_memory)),
+        format!("{}/s", ByteSize(info.written_bytes)),
+        format!("{}", ByteSize(info.total_written_bytes)),
+        format!("{}/s", ByteSize(info.read_bytes)),
+        format!("{}", ByteSize(info.total_read_bytes)),
+    ]
+}
+
+pub fn proc_info_table(proc_infos: &[ProcInfo], wide: bool) {
+    // Optional columns, dropped in this order when the table exceeds the terminal width
+    const OPTIONAL_COLUMNS: [&[usize]; 2] = [&[9, 10, 11, 12], &[8]];
+
+    let header = PROC_INFO_HEADER;
+    let rows: Vec<Vec<String>> = proc_infos
+        .iter()
+        .map(|info| proc_info_row(info, "%F %T", Some(30)))
+        .collect();
+
+    let width = table_width();
+    let mut hidden = Vec::new();
+    if !wide {
+        for columns in OPTIONAL_COLUMNS {
+            if natural_width(&header, &rows, &hidden) <= width as usize {
+                break;
+            }
+            hidden.extend_from_slice(columns);
+        }
+    }
+    let visible = |row: Vec<String>| {
+        row.into_iter()
+            .enumerate()
+            .filter(|(idx, _)| !hidden.contains(idx))
+            .map(|(_, cell)| cell)
+            .collect::<Vec<_>>()
+    };
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .set_header(visible(header.iter().map(|h| h.to_string()).collect()));
+    if wide {
+        table.set_content_arrangement(ContentArrangement::Disabled);
+    } else {
+        table
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_width(width);
+    }
+    table.add_rows(rows.into_iter().map(visible));
+
+    println!("{table}");
+}
+
+/// Columns of `jobs` command without `--format` option
+pub const DEFAULT_JOB_COLUMNS: &[JobColumn] = &[
+    JobColumn::Id,
+    JobColumn::Command,
+    JobColumn::At,
+    JobColumn::Next,
+    JobColumn::Output,
+];
+
+fn job_info_header(columns: &[JobColumn]) -> Vec<&'static str> {
+    columns
+        .iter()
+        .map(|column| match column {
+            JobColumn::Id => "Job",
+            JobColumn::Type => "Type",
+            JobColumn::Command => "Command",
+            JobColumn::At => "At",
+            JobColumn::Next => "Next run",
+            JobColumn::Restart => "Restart",
+            JobColumn::Output => "Output",
+        })
+        .collect()
+}
+
+/// Cells of a `jobs` row with command clipped to `clip`
+fn job_info_row(job: &Job, columns: &[JobColumn], clip: Option<usize>) -> Vec<String> {
     const EMPTY: String = String::new();
 
+    let mut command = match &job.info.job_type {
+        JobType::Shell => job.info.args.join(" "),
+        JobType::Service(s) if job.info.wrap.is_empty() => s.clone(),
+        JobType::Service(s) => format!("{} {s}", job.info.wrap.join(" ")),
+        JobType::Cron(_) => job.info.args.join(" "),
+        JobType::CronRestart(_, service) => format!("restart {service}"),
+    };
+    if let Some(max_len) = clip {
+        command = clip_str(&command, max_len);
+    }
+    let at = match &job.info.job_type {
+        JobType::Cron(at) | JobType::CronRestart(at, _) => at,
+        _ => &EMPTY,
+    };
+    let job_type = match &job.info.job_type {
+        JobType::Shell => "Shell",
+        JobType::Service(_) => "Service",
+        JobType::Cron(_) => "Cron",
+        JobType::CronRestart(_, _) => "CronRestart",
+    };
+    let restart = &job.info.restart;
+    columns
+        .iter()
+        .map(|column| match column {
+            JobColumn::Id => format!("{}", job.id),
+            JobColumn::Type => job_type.to_string(),
+            JobColumn::Command => command.clone(),
+            JobColumn::At => at.to_string(),
+            JobColumn::Next => job
+                .next_run
+                .map(|ts| ts.format("%F %T").to_string())
+                .unwrap_or_default(),
+            JobColumn::Restart => match restart.policy {
+                Restart::Never => "Never".to_string(),
+                _ => format!("{:?} ({}ms)", restart.policy, restart.wait_time),
+            },
+            JobColumn::Output => format!(
+                "{} / {} lines",
+                ByteSize(job.output.bytes),
+                job.output.lines
+            ),
+        })
+        .collect()
+}
+
+pub fn job_info_table(jobs: &[Job], columns: &[JobColumn]) {
     let mut table = Table::new();
     table
         .load_preset(UTF8_FULL)
-        .set_header(vec![
-            "Job", "PID", "Status", "Command", "Start", "End", "Cpu", "Mem", "Virt", "Write",
-            "Total", "Read", "Total",
-        ])
+        .set_header(job_info_header(columns))
         .set_content_arrangement(ContentArrangement::DynamicFullWidth)
-        .add_rows(proc_infos.iter().map(|info| {
-            let status = match &info.state {
-                ProcStatus::ExitOk => "Success".to_string(),
-                ProcStatus::ExitErr(code) => format!("Error {code}"),
-                ProcStatus::Unknown(err) => clip_str(err, 20),
-                st => format!("{st:?}"),
-            };
-            let command = info.cmd_args.join(" ");
-            let end = if let Some(ts) = info.end {
-                format!("{}", ts.format("%F %T"))
-            } else {
-                EMPTY
-            };
-            vec![
-                format!("{}", info.job_id),
-                format!("{}", info.pid),
-                status,
-                clip_str(&command, 30),
-                format!("{}", info.start.format("%F %T")),
-                end,
-                format!("{:.1}%", info.cpu),
-                format!("{}", ByteSize(info.memory)),
-                format!("{}", ByteSize(info.virtual_memory)),
-                format!("{}/s", ByteSize(info.written_bytes)),
-                format!("{}", ByteSize(info.total_written_bytes)),
-                format!("{}/s", ByteSize(info.read_bytes)),
-                format!("{}", ByteSize(info.total_read_bytes)),
-            ]
-        }));
+        .add_rows(jobs.iter().map(|job| job_info_row(job, columns, Some(30))));
 
     println!("{table}");
 }
 
-pub fn job_info_table(jobs: &[Job]) {
-    const EMPTY: String = String::new();
+/// Quote CSV field according to RFC 4180
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn print_csv_record<S: AsRef<str>>(fields: &[S]) {
+    let record: Vec<String> = fields.iter().map(|f| csv_field(f.as_ref())).collect();
+    // RFC 4180 line break
+    print!("{}\r\n", record.join(","));
+}
+
+/// Sortable ISO 8601 timestamp format for CSV output
+const CSV_TS_FORMAT: &str = "%FT%T%.3f%:z";
+
+pub fn proc_info_csv(proc_infos: &[ProcInfo]) {
+    print_csv_record(&PROC_INFO_HEADER);
+    for info in proc_infos {
+        print_csv_record(&proc_info_row(info, CSV_TS_FORMAT, None));
+    }
+}
+
+pub fn job_info_csv(jobs: &[Job], columns: &[JobColumn]) {
+    print_csv_record(&job_info_header(columns));
+    for job in jobs {
+        print_csv_record(&job_info_row(job, columns, None));
+    }
+}
 
+pub fn groups_table(groups: &BTreeMap<String, Vec<String>>) {
     let mut table = Table::new();
     table
         .load_preset(UTF8_FULL)
-        .set_header(vec!["Job", "Command", "At"])
+        .set_header(["Group", "Services"])
         .set_content_arrangement(ContentArrangement::DynamicFullWidth)
-        .add_rows(jobs.iter().map(|job| {
-            let command = match &job.info.job_type {
-                JobType::Shell => &job.info.args.join(" "),
-                JobType::Service(s) => s,
-                JobType::Cron(_) => &job.info.args.join(" "),
-            };
-            let at = if let JobType::Cron(at) = &job.info.job_type {
-                at
-            } else {
-                &EMPTY
-            };
-            vec![format!("{}", job.id), clip_str(command, 30), at.to_string()]
-        }));
+        .add_rows(
+            groups
+                .iter()
+                .map(|(group, recipes)| [group.clone(), recipes.join(", ")]),
+        );
 
     println!("{table}");
 }
+
+pub fn daemon_status_info(status: &DaemonStatus) {
+    let uptime = (Local::now() - status.started)
+        .to_std()
+        .map(|d| Duration::from_secs(d.as_secs()))
+        .unwrap_or_default();
+    println!("Pid:                     {}", status.pid);
+    println!("Version:                 {}", status.version);
+    println!(
+        "Started:                 {}",
+        status.started.format("%F %T")
+    );
+    println!(
+        "Uptime:                  {}",
+        humantime::format_duration(uptime)
+    );
+    println!("Jobs:                    {}", status.jobs);
+    println!("Running processes:       {}", status.running);
+    println!("Pending exit events:     {}", status.pending_exits);
+}
+
+pub fn exit_reason_info(reason: &ExitReason) {
+    let cause = match reason.state {
+        ProcStatus::ExitOk => "Exited successfully".to_string(),
+        // Processes terminated by a signal have no exit code
+        ProcStatus::ExitErr(0) => "Terminated by signal".to_string(),
+        ProcStatus::ExitErr(code) => format!("Exited with code {code}"),
+        ref state => format!("{state:?}"),
+    };
+    println!("Job:                     {}", reason.job_id);
+    println!("Reason:                  {cause}");
+    println!("Terminated:              {}", reason.end.format("%F %T"));
+}
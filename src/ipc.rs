@@ -4,6 +4,8 @@ use interprocess::local_socket::{prelude::*, GenericFilePath, ListenerOptions};
 use log::debug;
 use std::io;
 use std::io::prelude::*;
+use std::thread;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,6 +14,8 @@ pub enum IpcServerError {
     BindError(io::Error),
     #[error("Failed to resolve socket name: {0}")]
     SocketNameError(io::Error),
+    #[error("Socket path `{0}` exists and is not a socket")]
+    SocketPathError(String),
 }
 
 #[derive(Error, Debug)]
@@ -94,6 +98,31 @@ pub fn start_ipc_listener<F: FnMut(IpcStream) + Send + 'static>(
     Ok(())
 }
 
+/// Remove socket file left by a terminated listener.
+///
+/// Refuses to remove regular files or symlinks at the socket path.
+#[cfg(target_family = "unix")]
+pub fn remove_stale_socket(socket: &str) -> Result<(), IpcServerError> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(socket) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(socket).ok();
+            Ok(())
+        }
+        Ok(_) => Err(IpcServerError::SocketPathError(socket.to_string())),
+        Err(_) => Ok(()),
+    }
+}
+
+#[cfg(target_family = "windows")]
+pub fn remove_stale_socket(_socket: &str) -> Result<(), IpcServerError> {
+    // Named pipes are removed with their last handle
+    Ok(())
+}
+
+/// Delay before first retry of a refused connection
+const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(20);
+
 /// Connect to the socket and return the stream.
 fn ipc_client_connect(socket_name: &str) -> Result<LocalSocketStream, IpcClientError> {
     let name = socket_name
@@ -147,6 +176,72 @@ pub struct IpcStream {
 }
 
 impl IpcStream {
+    /// User id of the connected peer process
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn peer_uid(&self) -> Option<u32> {
+        use std::os::fd::{AsFd, AsRawFd};
+        let LocalSocketStream::UdSocket(stream) = &self.stream;
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: `cred` and `len` are valid for writes of the size passed in `len`
+        let res = unsafe {
+            libc::getsockopt(
+                stream.as_fd().as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        (res == 0).then_some(cred.uid)
+    }
+    /// User id of the connected peer process
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))]
+    pub fn peer_uid(&self) -> Option<u32> {
+        use std::os::fd::{AsFd, AsRawFd};
+        let LocalSocketStream::UdSocket(stream) = &self.stream;
+        let mut uid = 0;
+        let mut gid = 0;
+        // SAFETY: `uid` and `gid` are valid for writes
+        let res = unsafe { libc::getpeereid(stream.as_fd().as_raw_fd(), &mut uid, &mut gid) };
+        (res == 0).then_some(uid)
+    }
+    /// User id of the connected peer process (not supported on this platform)
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )))]
+    pub fn peer_uid(&self) -> Option<u32> {
+        None
+    }
+    /// Check if the peer process is running as the current user or one of `allowed_uids`.
+    /// Peers with unknown user id are rejected.
+    pub fn peer_allowed(&self, allowed_uids: &[u32]) -> bool {
+        // SAFETY: getuid has no preconditions and always succeeds
+        #[cfg(unix)]
+        let own_uid = Some(unsafe { libc::getuid() });
+        #[cfg(not(unix))]
+        let own_uid = None;
+        self.peer_uid()
+            .is_some_and(|uid| Some(uid) == own_uid || allowed_uids.contains(&uid))
+    }
     /// Connects to the socket and return the stream
     pub fn connect(logname: &str) -> Result<Self, IpcClientError> {
         let socket_name = IpcStream::user_socket_name();
@@ -157,6 +252,24 @@ impl IpcStream {
             stream,
         })
     }
+    /// Connects to the socket, retrying with increasing delays while the connection is refused
+    /// (e.g. during startup of the background process)
+    pub fn connect_with_retry(logname: &str, retries: u32) -> Result<Self, IpcClientError> {
+        let mut delay = CONNECT_RETRY_DELAY;
+        for _ in 0..retries {
+            match IpcStream::connect(logname) {
+                Err(IpcClientError::ConnectError(e))
+                    if e.kind() == io::ErrorKind::ConnectionRefused =>
+                {
+                    debug!(target: logname, "Connection refused - retrying in {delay:?}");
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                res => return res,
+            }
+        }
+        IpcStream::connect(logname)
+    }
     /// Check socket connection
     pub fn check_connection() -> Result<(), IpcClientError> {
         IpcStream::connect("check_connection")?;
@@ -1,6 +1,12 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use std::{env, fs};
 use thiserror::Error;
 
 pub struct Justfile {
@@ -15,39 +21,54 @@ struct JustfileDump {
     // doc: null,
     // first: Option<String>,
     // groups: [],
-    // modules: {},
+    /// Submodules with the same structure
+    #[serde(default)]
+    modules: HashMap<String, JustfileDump>,
+    /// Recipes by name (by namepath including recipes of modules after parsing)
     recipes: HashMap<String, JustfileRecipe>,
-    // settings: HashMap<String, serde_json::Value>,
-    //   "allow_duplicate_recipes": false,
-    //   "allow_duplicate_variables": false,
-    //   "dotenv_filename": null,
-    //   "dotenv_load": false,
-    //   "dotenv_path": null,
-    //   "dotenv_required": false,
-    //   "export": false,
-    //   "fallback": false,
-    //   "ignore_comments": false,
-    //   "positional_arguments": false,
-    //   "quiet": false,
-    //   "shell": null,
-    //   "tempdir": null,
-    //   "unstable": false,
-    //   "windows_powershell": false,
-    //   "windows_shell": null,
-    //   "working_directory": null
-    //  unexports: [],
-    //  warnings: []
+    #[serde(default)]
+    settings: JustfileSettings,
+    // unexports: [],
+    // warnings: []
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct JustfileSettings {
+    // allow_duplicate_recipes: false,
+    // allow_duplicate_variables: false,
+    // dotenv_filename: null,
+    // dotenv_load: false,
+    // dotenv_path: null,
+    // dotenv_required: false,
+    // export: false,
+    // fallback: false,
+    // ignore_comments: false,
+    // positional_arguments: false,
+    // quiet: false,
+    // shell: null,
+    // tempdir: null,
+    // unstable: false,
+    // windows_powershell: false,
+    // windows_shell: null,
+    /// Working directory of recipes, relative to justfile directory
+    working_directory: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
 struct JustfileRecipe {
     attributes: Vec<HashMap<String, String>>,
     //   "group": "autostart"
-    // body: [...],
-    // dependencies: [],
+    //   "env": "PORT=${API_PORT}"
+    //   "readiness": "tcp://127.0.0.1:5432"
+    #[serde(default)]
+    body: serde_json::Value,
+    #[serde(default)]
+    dependencies: Vec<JustfileDependency>,
     // doc: null,
     name: String,
-    // namepath: String,
+    /// Path including modules (e.g. `db::migrate`), missing in older just versions
+    #[serde(default)]
+    namepath: String,
     // parameters: [],
     // priors: 0,
     // private: false,
@@ -55,14 +76,122 @@ struct JustfileRecipe {
     // shebang: true
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+struct JustfileDependency {
+    /// Recipe name, relative to the module of the dependent recipe
+    recipe: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
 #[derive(Error, Debug)]
 pub enum JustfileError {
     #[error("Error in calling just executable: {0}")]
     SpawnError(#[from] std::io::Error),
+    #[error("Invalid justfile: {0}")]
+    ParseError(String),
     #[error("Invalid characters in justfile: {0}")]
     Utf8Error(#[from] std::string::FromUtf8Error),
-    #[error("justfile version mismatch: {0}")]
-    JsonError(#[from] serde_json::error::Error),
+    #[error("Unsupported output of just {version} at `{path}`: {error} (upgrade or downgrade just or shell-compose)")]
+    SchemaError {
+        version: String,
+        path: String,
+        error: serde_json::error::Error,
+    },
+    #[error("Invalid readiness probe `{0}`: {1}")]
+    ReadinessError(String, String),
+    #[error("Dependency cycle: {0}")]
+    DependencyCycleError(String),
+}
+
+/// Readiness probe of a service
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub enum Readiness {
+    /// Address accepting TCP connections (`tcp://HOST:PORT`)
+    Tcp(String),
+    /// Regular expression matching a log line (`log:REGEX`)
+    Log(String),
+}
+
+impl Readiness {
+    fn parse(probe: &str) -> Result<Self, JustfileError> {
+        let error = |msg: &str| JustfileError::ReadinessError(probe.to_string(), msg.to_string());
+        if let Some(addr) = probe.strip_prefix("tcp://") {
+            if addr.rsplit_once(':').is_none() {
+                return Err(error("expected `tcp://HOST:PORT`"));
+            }
+            Ok(Readiness::Tcp(addr.to_string()))
+        } else if let Some(pattern) = probe.strip_prefix("log:") {
+            Regex::new(pattern).map_err(|e| error(&e.to_string()))?;
+            Ok(Readiness::Log(pattern.to_string()))
+        } else {
+            Err(error("expected `tcp://HOST:PORT` or `log:REGEX`"))
+        }
+    }
+}
+
+/// Directory of justfile found by `just` in current directory or its ancestors
+fn justfile_directory() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .find(|dir| {
+            fs::read_dir(dir).is_ok_and(|mut entries| {
+                entries.any(|entry| {
+                    entry.is_ok_and(|entry| {
+                        let name = entry.file_name();
+                        name.eq_ignore_ascii_case("justfile")
+                            || name.eq_ignore_ascii_case(".justfile")
+                    })
+                })
+            })
+        })
+        .map(Path::to_path_buf)
+}
+
+/// Version reported by `just --version` (e.g. `1.36.0`)
+fn just_version() -> String {
+    Command::new("just")
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|out| out.split_whitespace().nth(1).map(str::to_string))
+        .unwrap_or_else(|| "(unknown version)".to_string())
+}
+
+/// Number of retries for parsing a justfile, which could be in the middle of being saved
+const PARSE_RETRIES: u32 = 2;
+const PARSE_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+impl JustfileDump {
+    /// Move recipes of modules into top-level recipes, keyed by their namepath
+    fn flatten_modules(&mut self) {
+        for (_name, mut module) in self.modules.drain() {
+            module.flatten_modules();
+            self.recipes.extend(module.recipes);
+        }
+        self.recipes = self
+            .recipes
+            .drain()
+            .map(|(_name, mut recipe)| {
+                if recipe.namepath.is_empty() {
+                    recipe.namepath = recipe.name.clone();
+                }
+                (recipe.namepath.clone(), recipe)
+            })
+            .collect();
+    }
+    /// Resolve dependencies of module recipes to their namepath.
+    /// Called once after [`JustfileDump::flatten_modules`], which processes nested modules repeatedly.
+    fn qualify_dependencies(&mut self) {
+        for recipe in self.recipes.values_mut() {
+            if let Some((module, _name)) = recipe.namepath.rsplit_once("::") {
+                for dependency in &mut recipe.dependencies {
+                    dependency.recipe = format!("{module}::{}", dependency.recipe);
+                }
+            }
+        }
+    }
 }
 
 impl Justfile {
@@ -70,11 +199,46 @@ impl Justfile {
         let output = Command::new("just")
             .args(["--dump", "--dump-format", "json"])
             .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(JustfileError::ParseError(stderr.trim().to_string()));
+        }
         let jsonstr = String::from_utf8(output.stdout)?;
-        let justfile = serde_json::from_str(&jsonstr)?;
+        Justfile::from_json(&jsonstr)
+    }
+    /// Read output of `just --dump --dump-format json`
+    fn from_json(jsonstr: &str) -> Result<Self, JustfileError> {
+        let deserializer = &mut serde_json::Deserializer::from_str(jsonstr);
+        let mut justfile: JustfileDump =
+            serde_path_to_error::deserialize(deserializer).map_err(|e| {
+                JustfileError::SchemaError {
+                    version: just_version(),
+                    path: e.path().to_string(),
+                    error: e.into_inner(),
+                }
+            })?;
+        justfile.flatten_modules();
+        justfile.qualify_dependencies();
         let just = Justfile { justfile };
         Ok(just)
     }
+    /// Parse justfile, retrying on parse errors
+    pub fn parse_with_retry() -> Result<Self, JustfileError> {
+        let mut retries = 0;
+        loop {
+            match Justfile::parse() {
+                Err(e) if retries < PARSE_RETRIES && !matches!(e, JustfileError::SpawnError(_)) => {
+                    info!("Parsing justfile failed ({e}) - retrying");
+                    thread::sleep(PARSE_RETRY_DELAY);
+                    retries += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+    pub fn has_recipe(&self, name: &str) -> bool {
+        self.justfile.recipes.contains_key(name)
+    }
     pub fn group_recipes(&self, group: &str) -> Vec<String> {
         let recipes = self.justfile.recipes.values().filter(|recipe| {
             recipe
@@ -82,6 +246,255 @@ impl Justfile {
                 .iter()
                 .any(|attr| attr.get("group").map(|g| g == group).unwrap_or(false))
         });
-        recipes.map(|recipe| recipe.name.clone()).collect()
+        recipes.map(|recipe| recipe.namepath.clone()).collect()
+    }
+    /// Recipes of a group and their dependencies, with dependencies first
+    pub fn ordered_group_recipes(&self, group: &str) -> Result<Vec<String>, JustfileError> {
+        let mut recipes = self.group_recipes(group);
+        recipes.sort();
+        let mut ordered = Vec::new();
+        let mut visiting = Vec::new();
+        for name in &recipes {
+            self.visit_dependencies(name, &mut visiting, &mut ordered)?;
+        }
+        Ok(ordered)
+    }
+    /// Partition recipes in dependency order (see [`Justfile::ordered_group_recipes`])
+    /// into stages, which only depend on recipes of earlier stages
+    pub fn dependency_stages(&self, ordered: &[String]) -> Vec<Vec<String>> {
+        let mut depths: HashMap<&str, usize> = HashMap::new();
+        let mut stages: Vec<Vec<String>> = Vec::new();
+        for name in ordered {
+            let depth = self
+                .justfile
+                .recipes
+                .get(name)
+                .into_iter()
+                .flat_map(|recipe| &recipe.dependencies)
+                .filter_map(|dependency| depths.get(dependency.recipe.as_str()))
+                .map(|depth| depth + 1)
+                .max()
+                .unwrap_or(0);
+            depths.insert(name, depth);
+            if stages.len() <= depth {
+                stages.resize(depth + 1, Vec::new());
+            }
+            stages[depth].push(name.clone());
+        }
+        stages
+    }
+    /// Depth-first traversal adding dependencies before the recipe itself
+    fn visit_dependencies(
+        &self,
+        name: &str,
+        visiting: &mut Vec<String>,
+        ordered: &mut Vec<String>,
+    ) -> Result<(), JustfileError> {
+        if ordered.iter().any(|recipe| recipe == name) {
+            return Ok(());
+        }
+        if let Some(pos) = visiting.iter().position(|recipe| recipe == name) {
+            let mut cycle = visiting[pos..].to_vec();
+            cycle.push(name.to_string());
+            return Err(JustfileError::DependencyCycleError(cycle.join(" -> ")));
+        }
+        let Some(recipe) = self.justfile.recipes.get(name) else {
+            return Ok(());
+        };
+        visiting.push(name.to_string());
+        for dependency in &recipe.dependencies {
+            self.visit_dependencies(&dependency.recipe, visiting, ordered)?;
+        }
+        visiting.pop();
+        ordered.push(name.to_string());
+        Ok(())
+    }
+    /// Environment variables of `env` attributes (`KEY=VALUE`) of a recipe.
+    /// Values may contain `${VAR}` placeholders, which are resolved when the service is started.
+    pub fn recipe_env(&self, name: &str) -> Vec<(String, String)> {
+        let Some(recipe) = self.justfile.recipes.get(name) else {
+            return Vec::new();
+        };
+        recipe
+            .attributes
+            .iter()
+            .filter_map(|attr| attr.get("env"))
+            .filter_map(|var| var.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+    /// Environment variables of all recipes of a group.
+    /// Recipes are visited by name and the first definition of a variable is used.
+    pub fn group_env(&self, group: &str) -> Vec<(String, String)> {
+        let mut recipes = self.group_recipes(group);
+        recipes.sort();
+        let mut env: Vec<(String, String)> = Vec::new();
+        for (key, value) in recipes.iter().flat_map(|name| self.recipe_env(name)) {
+            if !env.iter().any(|(k, _)| *k == key) {
+                env.push((key, value));
+            }
+        }
+        env
+    }
+    /// Output file of `log_file` attribute of a recipe, relative to the working directory
+    pub fn recipe_log_file(&self, name: &str) -> Option<PathBuf> {
+        let recipe = self.justfile.recipes.get(name)?;
+        recipe
+            .attributes
+            .iter()
+            .find_map(|attr| attr.get("log_file"))
+            .map(PathBuf::from)
+    }
+    /// Readiness probe of `readiness` attribute of a recipe
+    pub fn recipe_readiness(&self, name: &str) -> Result<Option<Readiness>, JustfileError> {
+        self.justfile
+            .recipes
+            .get(name)
+            .and_then(|recipe| {
+                recipe
+                    .attributes
+                    .iter()
+                    .find_map(|attr| attr.get("readiness"))
+            })
+            .map(|probe| Readiness::parse(probe))
+            .transpose()
+    }
+    /// Recipe body and dependencies, for detecting changes of a recipe
+    pub fn recipe_definition(&self, name: &str) -> Option<String> {
+        let recipe = self.justfile.recipes.get(name)?;
+        let dependencies = serde_json::to_string(&recipe.dependencies).ok()?;
+        Some(format!("{} {dependencies}", recipe.body))
+    }
+    /// Working directory of recipes
+    pub fn working_directory(&self) -> Option<PathBuf> {
+        let dir = justfile_directory()?;
+        match &self.justfile.settings.working_directory {
+            Some(working_directory) => Some(dir.join(working_directory)),
+            None => Some(dir),
+        }
+    }
+    /// All groups with their recipes (sorted by name)
+    pub fn groups(&self) -> BTreeMap<String, Vec<String>> {
+        let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for recipe in self.justfile.recipes.values() {
+            for group in recipe
+                .attributes
+                .iter()
+                .filter_map(|attr| attr.get("group"))
+            {
+                groups
+                    .entry(group.clone())
+                    .or_default()
+                    .push(recipe.namepath.clone());
+            }
+        }
+        for recipes in groups.values_mut() {
+            recipes.sort();
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn justfile(recipes: &[(&str, &str, &[&str])]) -> Justfile {
+        let recipes: serde_json::Map<String, serde_json::Value> = recipes
+            .iter()
+            .map(|(name, group, dependencies)| {
+                let recipe = serde_json::json!({
+                    "attributes": [{"group": group}],
+                    "name": name,
+                    "namepath": name,
+                    "dependencies": dependencies
+                        .iter()
+                        .map(|dependency| serde_json::json!({"recipe": dependency, "arguments": []}))
+                        .collect::<Vec<_>>(),
+                });
+                (name.to_string(), recipe)
+            })
+            .collect();
+        Justfile::from_json(&serde_json::json!({ "recipes": recipes }).to_string()).unwrap()
+    }
+
+    #[test]
+    fn dependencies_first() {
+        let just = justfile(&[
+            ("api", "web", &["db", "cache"]),
+            ("db", "web", &[]),
+            ("cache", "infra", &["db"]),
+            ("frontend", "web", &["api"]),
+            ("worker", "bg", &["db"]),
+        ]);
+        let ordered = just.ordered_group_recipes("web").unwrap();
+        // Includes dependency `cache` outside of group, but not `worker`
+        assert_eq!(ordered, ["db", "cache", "api", "frontend"]);
+        assert_eq!(
+            just.dependency_stages(&ordered),
+            [vec!["db"], vec!["cache"], vec!["api"], vec!["frontend"]]
+        );
+        assert!(just.ordered_group_recipes("unknown").unwrap().is_empty());
+    }
+
+    #[test]
+    fn independent_recipes_in_same_stage() {
+        let just = justfile(&[
+            ("a", "g", &["base"]),
+            ("b", "g", &["base"]),
+            ("base", "g", &[]),
+            ("single", "g", &[]),
+        ]);
+        let ordered = just.ordered_group_recipes("g").unwrap();
+        assert_eq!(ordered, ["base", "a", "b", "single"]);
+        assert_eq!(
+            just.dependency_stages(&ordered),
+            [vec!["base", "single"], vec!["a", "b"]]
+        );
+    }
+
+    #[test]
+    fn unknown_dependency_ignored() {
+        let just = justfile(&[("api", "web", &["missing"])]);
+        assert_eq!(just.ordered_group_recipes("web").unwrap(), ["api"]);
+    }
+
+    #[test]
+    fn dependency_cycle() {
+        let just = justfile(&[
+            ("a", "g", &["b"]),
+            ("b", "other", &["c"]),
+            ("c", "other", &["a"]),
+        ]);
+        let err = just.ordered_group_recipes("g").unwrap_err();
+        assert!(
+            matches!(&err, JustfileError::DependencyCycleError(cycle) if cycle == "a -> b -> c -> a"),
+            "{err}"
+        );
+        let just = justfile(&[("a", "g", &["a"])]);
+        assert!(matches!(
+            just.ordered_group_recipes("g"),
+            Err(JustfileError::DependencyCycleError(cycle)) if cycle == "a -> a"
+        ));
+    }
+
+    #[test]
+    fn module_dependencies() {
+        let json = r#"{
+            "recipes": {},
+            "modules": {"db": {
+                "recipes": {
+                    "migrate": {"attributes": [{"group": "web"}], "name": "migrate",
+                        "namepath": "db::migrate", "dependencies": [{"recipe": "server"}]},
+                    "server": {"attributes": [], "name": "server",
+                        "namepath": "db::server", "dependencies": []}
+                }
+            }}
+        }"#;
+        let just = Justfile::from_json(json).unwrap();
+        assert_eq!(
+            just.ordered_group_recipes("web").unwrap(),
+            ["db::server", "db::migrate"]
+        );
     }
 }
@@ -1,13 +1,19 @@
+mod audit;
 mod command;
 mod dispatcher;
 mod display;
 mod ipc;
 mod justfile;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod runner;
 
+pub use audit::*;
 pub use command::*;
 pub use dispatcher::*;
 pub use display::*;
 pub use ipc::*;
 pub use justfile::*;
+#[cfg(feature = "metrics")]
+pub use metrics::*;
 pub use runner::*;
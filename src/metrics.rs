@@ -0,0 +1,142 @@
+use crate::{proc_stats, ProcInfo, Runner};
+use log::{error, info};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use sysinfo::{ProcessRefreshKind, RefreshKind, System};
+
+/// Serve process metrics in Prometheus text format at `http://<addr>/metrics`
+pub fn start_metrics_listener(
+    addr: SocketAddr,
+    procs: Arc<Mutex<Vec<Runner>>>,
+) -> Result<(), io::Error> {
+    let listener = TcpListener::bind(addr)?;
+    info!("Serving metrics on http://{addr}/metrics");
+    thread::spawn(move || {
+        let mut system = System::new_with_specifics(
+            RefreshKind::new().with_processes(ProcessRefreshKind::new()),
+        );
+        for stream in listener.incoming() {
+            let res = stream.and_then(|stream| handle_request(stream, &mut system, &procs));
+            if let Err(e) = res {
+                error!("Metrics request failed: {e}");
+            }
+        }
+    });
+    Ok(())
+}
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn handle_request(
+    mut stream: TcpStream,
+    system: &mut System,
+    procs: &Mutex<Vec<Runner>>,
+) -> Result<(), io::Error> {
+    // Don't block further requests by idle connections
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            metrics_text(&proc_stats(
+                system,
+                procs,
+                sysinfo::MINIMUM_CPU_UPDATE_INTERVAL,
+            )),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+struct Metric {
+    name: &'static str,
+    /// Prometheus metric type
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&ProcInfo) -> f64,
+}
+
+const METRICS: [Metric; 6] = [
+    Metric {
+        name: "up",
+        kind: "gauge",
+        help: "Process is running (1) or terminated (0)",
+        value: |info| f64::from(u8::from(!info.state.exited())),
+    },
+    Metric {
+        name: "runs_total",
+        kind: "counter",
+        help: "Number of spawned processes of the job",
+        value: |info| f64::from(info.run),
+    },
+    Metric {
+        name: "cpu_usage_percent",
+        kind: "gauge",
+        help: "CPU usage of the process",
+        value: |info| f64::from(info.cpu),
+    },
+    Metric {
+        name: "memory_bytes",
+        kind: "gauge",
+        help: "Memory usage of the process",
+        value: |info| info.memory as f64,
+    },
+    Metric {
+        name: "virtual_memory_bytes",
+        kind: "gauge",
+        help: "Virtual memory usage of the process",
+        value: |info| info.virtual_memory as f64,
+    },
+    Metric {
+        name: "written_bytes_total",
+        kind: "counter",
+        help: "Bytes written to disk by the process",
+        value: |info| info.total_written_bytes as f64,
+    },
+];
+
+/// Prometheus text exposition of the latest process of each job
+fn metrics_text(proc_infos: &[ProcInfo]) -> String {
+    // Process infos are ordered from newest to oldest
+    let mut latest: Vec<&ProcInfo> = Vec::new();
+    for info in proc_infos {
+        if !latest.iter().any(|l| l.job_id == info.job_id) {
+            latest.push(info);
+        }
+    }
+    latest.sort_by_key(|info| info.job_id);
+
+    let mut text = String::new();
+    for Metric {
+        name,
+        kind,
+        help,
+        value,
+    } in METRICS
+    {
+        let _ = writeln!(text, "# HELP shell_compose_job_{name} {help}");
+        let _ = writeln!(text, "# TYPE shell_compose_job_{name} {kind}");
+        for info in &latest {
+            let _ = writeln!(
+                text,
+                "shell_compose_job_{name}{{job=\"{}\",pid=\"{}\"}} {}",
+                info.job_id,
+                info.pid,
+                value(info)
+            );
+        }
+    }
+    text
+}
@@ -1,22 +1,182 @@
-use crate::{DispatcherError, Formatter, JobId, Pid, RestartInfo};
-use chrono::{DateTime, Local};
-use log::info;
+use crate::{
+    AuditEvent, AuditLog, DispatcherError, Formatter, JobId, LogStreams, Pid, RestartInfo,
+    TerminationLog,
+};
+use chrono::{DateTime, Local, TimeDelta};
+use chrono_tz::Tz;
+use log::{error, info};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::io::{BufRead, BufReader, Read};
-use std::process::{self, Child, Command, Stdio};
-use std::sync::{mpsc, Arc, Mutex};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, Weak};
 use std::thread;
-use sysinfo::{ProcessRefreshKind, RefreshKind, System, UpdateKind, Users};
+use std::time::{Duration, Instant};
+use sysinfo::{
+    ProcessRefreshKind, ProcessStatus, ProcessesToUpdate, RefreshKind, Signal, System, UpdateKind,
+    Users,
+};
+
+/// Environment variable set in spawned processes, containing the job id
+pub const ACTIVE_ENV_VAR: &str = "SHELL_COMPOSE_ACTIVE";
+
+/// Default number of buffered log lines per process
+pub const DEFAULT_LOG_LINES: usize = 200;
+
+/// Start of log line added after process termination
+const TERMINATED_MARKER: &str = "<process terminated";
+
+/// Variables inherited from the daemon environment with `--clean-env`
+const CLEAN_ENV_VARS: &[&str] = &["PATH", "HOME", "SYSTEMROOT", "USERPROFILE"];
+
+fn is_clean_env_var(key: &OsStr) -> bool {
+    CLEAN_ENV_VARS
+        .iter()
+        .any(|var| key.eq_ignore_ascii_case(var))
+}
+
+/// Time after spawning, during which a running process is reported as starting
+const STARTUP_WINDOW: TimeDelta = TimeDelta::milliseconds(1000);
 
 /// Child process controller
 pub struct Runner {
     pub proc: Child,
     pub info: ProcInfo,
+    /// Additional environment variables
+    pub env: Vec<(String, String)>,
+    /// Working directory
+    pub cwd: Option<PathBuf>,
+    pub output_options: OutputOptions,
+    /// Complete environment, if recording is enabled
+    pub effective_env: Option<Vec<(String, String)>>,
     pub restart_info: RestartInfo,
     /// Flag set in stop/down command to prevent restart
     pub user_terminated: bool,
+    /// Flag set in pause command, reset in resume command
+    pub paused: bool,
+    /// Flag set in hold command, reset in unhold command, to suppress restarts
+    pub held: bool,
     pub output: Arc<Mutex<OutputBuffer>>,
+    /// Log file shared with output listeners
+    log_file: Option<Arc<Mutex<File>>>,
+}
+
+/// Buffering of captured output
+#[derive(Clone, Debug)]
+pub struct OutputOptions {
+    /// Maximal number of buffered lines
+    pub max_lines: Option<usize>,
+    pub streams: LogStreams,
+    /// File where captured lines are appended
+    pub log_file: Option<PathBuf>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            max_lines: Some(DEFAULT_LOG_LINES),
+            streams: LogStreams::Both,
+            log_file: None,
+        }
+    }
+}
+
+/// Destinations of lines read by an output listener
+struct OutputSink {
+    buffer: Arc<Mutex<OutputBuffer>>,
+    /// Log file shared by stdout and stderr listeners
+    log_file: Option<Arc<Mutex<File>>>,
+}
+
+/// Shared context of all runners
+#[derive(Clone)]
+pub struct RunnerContext {
+    /// Sender channel for notifying the child watcher about terminated processes
+    pub channel: mpsc::Sender<Pid>,
+    /// Number of notifications not yet processed by the child watcher
+    pub pending_exits: Arc<AtomicUsize>,
+    /// Maximal number of buffered log lines per process
+    pub log_lines: Option<usize>,
+    /// Memory limit for buffered log lines of all jobs
+    pub log_budget: Option<Arc<LogBudget>>,
+    /// Executables permitted to be spawned (names or absolute paths)
+    pub allowed_commands: Option<Arc<Vec<String>>>,
+    /// Start processes with a minimal environment instead of inheriting the daemon environment
+    pub clean_env: bool,
+    /// Record effective environment of spawned processes
+    pub record_env: bool,
+    /// Number of spawned processes per job
+    pub runs: Arc<Mutex<HashMap<JobId, u32>>>,
+    /// Final state and end time of the most recently terminated process per job
+    pub last_exit: Arc<Mutex<HashMap<JobId, ProcExit>>>,
+    /// Restart times of each job within the backoff window
+    pub restarts: Arc<Mutex<HashMap<JobId, VecDeque<DateTime<Local>>>>>,
+    /// Rate limiting of termination messages of each job
+    pub(crate) termination_log: Arc<Mutex<HashMap<JobId, TerminationLog>>>,
+    /// Record of process lifecycle events
+    pub audit_log: Option<Arc<AuditLog>>,
+}
+
+impl RunnerContext {
+    /// New command with environment according to daemon policy
+    fn command(&self, exe: &str) -> Command {
+        let mut cmd = Command::new(exe);
+        if self.clean_env {
+            cmd.env_clear();
+            for (key, value) in env::vars_os().filter(|(key, _)| is_clean_env_var(key)) {
+                cmd.env(key, value);
+            }
+        }
+        cmd
+    }
+    /// Reject programs not contained in the command allowlist, if configured
+    pub fn check_allowed(&self, exe: &str) -> Result<(), DispatcherError> {
+        match &self.allowed_commands {
+            Some(allowed) if !is_allowed_command(allowed, exe) => {
+                Err(DispatcherError::CommandNotAllowedError(exe.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Append event to audit log, if enabled
+    pub fn audit(&self, event: AuditEvent, info: &ProcInfo, exit_code: Option<i32>) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(event, info.job_id, info.pid, &info.cmd_args, exit_code);
+        }
+    }
+    /// Increment and return run number of job
+    fn next_run(&self, job_id: JobId) -> u32 {
+        let mut runs = self.runs.lock().expect("lock");
+        let run = runs.entry(job_id).or_default();
+        *run += 1;
+        *run
+    }
+    /// Environment of a process spawned with additional variables `env`
+    pub fn effective_env(&self, job_id: JobId, env: &[(String, String)]) -> Vec<(String, String)> {
+        let mut vars: BTreeMap<String, String> = env::vars_os()
+            .filter(|(key, _)| !self.clean_env || is_clean_env_var(key))
+            .map(|(key, value)| {
+                (
+                    key.to_string_lossy().to_string(),
+                    value.to_string_lossy().to_string(),
+                )
+            })
+            .collect();
+        vars.extend(env.iter().cloned());
+        vars.insert(ACTIVE_ENV_VAR.to_string(), job_id.to_string());
+        vars.into_iter().collect()
+    }
+    /// Notify watcher about terminated process
+    pub fn notify_exit(&self, pid: Pid) {
+        self.pending_exits.fetch_add(1, Ordering::Relaxed);
+        self.channel.send(pid).unwrap();
+    }
 }
 
 /// Process information
@@ -28,6 +188,10 @@ pub struct ProcInfo {
     pub state: ProcStatus,
     pub start: DateTime<Local>,
     pub end: Option<DateTime<Local>>,
+    /// Number of processes spawned for the job, including this one
+    pub run: u32,
+    /// Started with trigger command outside of cron schedule
+    pub manual: bool,
     /// Total CPU usage (in %)
     /// See <https://docs.rs/sysinfo/latest/i686-pc-windows-msvc/sysinfo/struct.Process.html#method.cpu_usage>
     pub cpu: f32,
@@ -54,18 +218,37 @@ impl ProcInfo {
     }
 }
 
+/// Final state and end time of a terminated process
+pub type ProcExit = (ProcStatus, DateTime<Local>);
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum ProcStatus {
     Spawned,
+    /// Running within startup window
+    Starting,
     Running,
+    /// Suspended with pause command
+    Paused,
     ExitOk,
     ExitErr(i32),
+    /// Not restarted anymore after too many failures
+    CrashLooping,
     Unknown(String),
 }
 
 impl ProcStatus {
     pub fn exited(&self) -> bool {
-        matches!(self, ProcStatus::ExitOk | ProcStatus::ExitErr(_))
+        matches!(
+            self,
+            ProcStatus::ExitOk | ProcStatus::ExitErr(_) | ProcStatus::CrashLooping
+        )
+    }
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            ProcStatus::ExitOk => Some(0),
+            ProcStatus::ExitErr(code) => Some(*code),
+            _ => None,
+        }
     }
 }
 
@@ -75,18 +258,126 @@ pub struct LogLine {
     pub ts: DateTime<Local>,
     pub job_id: JobId,
     pub pid: Pid,
+    /// Run number of the job process
+    pub run: u32,
     pub line: String,
     pub is_stderr: bool,
 }
 
 impl LogLine {
-    pub fn log(&self, formatter: &Formatter) {
-        let dt = self.ts.format("%F %T%.3f");
+    /// Marker line added after process termination
+    pub fn terminated(job_id: JobId, pid: Pid, run: u32, exit_code: Option<i32>) -> Self {
+        let line = match exit_code {
+            Some(code) => format!("{TERMINATED_MARKER}: exit code {code}>"),
+            None => format!("{TERMINATED_MARKER}>"),
+        };
+        LogLine {
+            ts: Local::now(),
+            job_id,
+            pid,
+            run,
+            line,
+            is_stderr: false,
+        }
+    }
+    pub fn is_termination_marker(&self) -> bool {
+        !self.is_stderr && self.line.starts_with(TERMINATED_MARKER)
+    }
+    pub fn log(&self, formatter: &Formatter, format: &LogLineFormat) {
+        let dt = match format.tz {
+            Some(tz) => self.ts.with_timezone(&tz).format("%F %T%.3f").to_string(),
+            None => self.ts.format("%F %T%.3f").to_string(),
+        };
+        let line = if format.sanitize {
+            sanitize_line(&self.line)
+        } else {
+            Cow::Borrowed(self.line.as_str())
+        };
+        let color = formatter.log_color_proc(self.job_id as usize, self.is_stderr);
+        let prefix = self.prefix(format.show_run);
+        let width = format.prefix_width;
+        println!("{color}{dt} {prefix:<width$} {line}{color:#}");
+    }
+    /// Process prefix like `[1|1234]`, or `[1#3|1234]` including the run number
+    fn prefix(&self, show_run: bool) -> String {
         let job_id = self.job_id;
         let pid = self.pid;
-        let line = &self.line;
-        let color = formatter.log_color_proc(job_id as usize, self.is_stderr);
-        println!("{color}{dt} [{job_id}|{pid}] {line}{color:#}");
+        if show_run {
+            let run = self.run;
+            format!("[{job_id}#{run}|{pid}]")
+        } else {
+            format!("[{job_id}|{pid}]")
+        }
+    }
+    /// Line with timestamp and process prefix, as written to log files
+    fn file_line(&self) -> String {
+        let dt = self.ts.format("%F %T%.3f");
+        format!("{dt} [{}|{}] {}\n", self.job_id, self.pid, self.line)
+    }
+}
+
+/// Display options of log lines
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LogLineFormat {
+    /// Timezone of timestamps (local time if not set)
+    pub tz: Option<Tz>,
+    /// Show run number in prefix (e.g. `[1#3|1234]`)
+    pub show_run: bool,
+    /// Escape control and invisible characters
+    pub sanitize: bool,
+    /// Minimal width of prefixes, for aligning lines of several processes
+    pub prefix_width: usize,
+}
+
+impl LogLineFormat {
+    /// Widen prefixes to the prefix of `line`. Lines printed before are not realigned.
+    pub fn align(&mut self, line: &LogLine) {
+        self.prefix_width = self.prefix_width.max(line.prefix(self.show_run).len());
+    }
+}
+
+/// Replace control characters (except tab) and invisible formatting characters
+/// with escape sequences, which can't mangle the terminal
+fn sanitize_line(line: &str) -> Cow<'_, str> {
+    fn is_unsafe(c: char) -> bool {
+        let invisible = matches!(
+            c,
+            '\u{200b}'..='\u{200f}' | '\u{2028}'..='\u{202e}' | '\u{2060}'..='\u{2064}' | '\u{feff}'
+        );
+        (c.is_control() && c != '\t') || invisible
+    }
+    if !line.contains(is_unsafe) {
+        return Cow::Borrowed(line);
+    }
+    let mut sanitized = String::with_capacity(line.len());
+    for c in line.chars() {
+        if is_unsafe(c) {
+            sanitized.extend(c.escape_default());
+        } else {
+            sanitized.push(c);
+        }
+    }
+    Cow::Owned(sanitized)
+}
+
+/// Total captured stdout/stderr output
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug)]
+pub struct OutputStats {
+    pub bytes: u64,
+    pub lines: u64,
+}
+
+impl OutputStats {
+    pub fn add(&mut self, line: &str) {
+        self.bytes += line.len() as u64 + 1;
+        self.lines += 1;
+    }
+}
+
+impl std::ops::AddAssign for OutputStats {
+    fn add_assign(&mut self, other: Self) {
+        self.bytes += other.bytes;
+        self.lines += other.lines;
     }
 }
 
@@ -94,29 +385,143 @@ impl LogLine {
 pub struct OutputBuffer {
     lines: VecDeque<LogLine>,
     max_len: Option<usize>,
+    /// Size of buffered lines in bytes
+    bytes: u64,
+    budget: Option<Arc<LogBudget>>,
+    /// Job id replacing the id of log lines after renumbering
+    job_id: Option<JobId>,
+    /// Run number of the process
+    pub run: u32,
+    /// Total output including removed lines
+    pub stats: OutputStats,
 }
 
 impl OutputBuffer {
-    pub fn new(max_len: Option<usize>) -> Self {
+    pub fn new(max_len: Option<usize>, budget: Option<Arc<LogBudget>>) -> Self {
         OutputBuffer {
             max_len,
             lines: VecDeque::new(),
+            bytes: 0,
+            budget,
+            job_id: None,
+            run: 0,
+            stats: OutputStats::default(),
         }
     }
-    pub fn push(&mut self, line: LogLine) {
+    pub fn push(&mut self, mut line: LogLine) {
+        if let Some(job_id) = self.job_id {
+            line.job_id = job_id;
+        }
+        let size = line.line.len() as u64;
+        self.bytes += size;
+        if let Some(budget) = &self.budget {
+            budget.used.fetch_add(size, Ordering::Relaxed);
+        }
         self.lines.push_back(line);
         if let Some(max_len) = self.max_len {
             if self.lines.len() > max_len {
-                let _ = self.lines.pop_front();
+                let _ = self.pop_front();
             }
         }
     }
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+    /// Change job id of buffered and future lines
+    pub fn set_job_id(&mut self, job_id: JobId) {
+        self.job_id = Some(job_id);
+        for line in self.lines.iter_mut() {
+            line.job_id = job_id;
+        }
+    }
+    /// Change maximal number of lines, removing oldest lines exceeding the new limit
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = Some(max_len);
+        while self.lines.len() > max_len {
+            let _ = self.pop_front();
+        }
+    }
+    /// Remove oldest line
+    pub fn pop_front(&mut self) -> Option<LogLine> {
+        let line = self.lines.pop_front()?;
+        let size = line.line.len() as u64;
+        self.bytes -= size;
+        if let Some(budget) = &self.budget {
+            budget.used.fetch_sub(size, Ordering::Relaxed);
+        }
+        Some(line)
+    }
+    pub fn lines(&self) -> impl Iterator<Item = &LogLine> {
+        self.lines.iter()
+    }
+    /// Timestamp of the line preceding the last `n` lines
+    pub fn tail_start(&self, n: usize) -> Option<DateTime<Local>> {
+        let idx = self.lines.len().checked_sub(n + 1)?;
+        Some(self.lines[idx].ts)
+    }
     pub fn lines_since(&self, last_seen: &mut DateTime<Local>) -> impl Iterator<Item = &LogLine> {
         let ts = *last_seen;
         if let Some(entry) = self.lines.back() {
             *last_seen = entry.ts;
         }
-        self.lines.iter().skip_while(move |entry| ts >= entry.ts)
+        // Lines are ordered by timestamp, so the buffer lock is not held for scanning all lines
+        let start = self.lines.partition_point(|entry| entry.ts <= ts);
+        self.lines.range(start..)
+    }
+}
+
+impl Drop for OutputBuffer {
+    fn drop(&mut self) {
+        if let Some(budget) = &self.budget {
+            budget.used.fetch_sub(self.bytes, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Memory limit for buffered log lines of all jobs
+pub struct LogBudget {
+    max_bytes: u64,
+    used: AtomicU64,
+    buffers: Mutex<Vec<Weak<Mutex<OutputBuffer>>>>,
+}
+
+impl LogBudget {
+    pub fn new(max_bytes: u64) -> Self {
+        LogBudget {
+            max_bytes,
+            used: AtomicU64::new(0),
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+    pub fn register(&self, buffer: &Arc<Mutex<OutputBuffer>>) {
+        self.buffers
+            .lock()
+            .expect("lock")
+            .push(Arc::downgrade(buffer));
+    }
+    pub fn exceeded(&self) -> bool {
+        self.used.load(Ordering::Relaxed) > self.max_bytes
+    }
+    /// Evict oldest lines of the largest buffers until the limit is met.
+    /// Must not be called while holding a buffer lock.
+    pub fn enforce(&self) {
+        let mut buffers = self.buffers.lock().expect("lock");
+        buffers.retain(|buffer| buffer.strong_count() > 0);
+        while self.exceeded() {
+            let Some(largest) = buffers
+                .iter()
+                .filter_map(Weak::upgrade)
+                .max_by_key(|buffer| buffer.lock().map(|b| b.bytes).unwrap_or(0))
+            else {
+                break;
+            };
+            let Ok(mut buffer) = largest.lock() else {
+                break;
+            };
+            if buffer.pop_front().is_none() {
+                break;
+            }
+        }
     }
 }
 
@@ -124,46 +529,91 @@ impl Runner {
     pub fn spawn(
         job_id: JobId,
         args: &[String],
+        env: &[(String, String)],
+        cwd: Option<&Path>,
         restart_info: RestartInfo,
-        channel: mpsc::Sender<Pid>,
+        output_options: OutputOptions,
+        ctx: &RunnerContext,
     ) -> Result<Self, DispatcherError> {
         let cmd_args = args.to_vec();
         let mut cmd = VecDeque::from(args.to_owned());
         let Some(exe) = cmd.pop_front() else {
             return Err(DispatcherError::EmptyProcCommandError);
         };
+        ctx.check_allowed(&exe)?;
         // info!("Spawning {exe} {cmd:?}");
 
-        let mut child = Command::new(exe)
+        let mut command = ctx.command(&exe);
+        if let Some(cwd) = cwd {
+            command.current_dir(cwd);
+        }
+        let mut child = command
             .args(cmd)
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .env(ACTIVE_ENV_VAR, job_id.to_string())
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
-            .map_err(DispatcherError::ProcSpawnError)?;
+            .map_err(spawn_error)?;
         let pid = child.id();
 
         // output listeners
-        let max_len = 200; // TODO: Make configurable
-        let output = Arc::new(Mutex::new(OutputBuffer::new(Some(max_len))));
+        let run = ctx.next_run(job_id);
+        let mut buffer = OutputBuffer::new(output_options.max_lines, ctx.log_budget.clone());
+        buffer.run = run;
+        let output = Arc::new(Mutex::new(buffer));
+        if let Some(budget) = &ctx.log_budget {
+            budget.register(&output);
+        }
+
+        // Failing to open the log file doesn't prevent the process from running
+        let log_file = output_options.log_file.as_ref().and_then(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| error!("Failed to open log file {}: {e}", path.display()))
+                .ok()
+                .map(|file| Arc::new(Mutex::new(file)))
+        });
 
-        let buffer = output.clone();
+        let sink = OutputSink {
+            buffer: output.clone(),
+            log_file: log_file.clone(),
+        };
         let stdout = child.stdout.take().unwrap();
+        let capture = output_options.streams.stdout();
+        let listener_ctx = ctx.clone();
         let _stdout_handle = thread::spawn(move || {
             output_listener(
                 BufReader::new(stdout),
                 job_id,
                 pid,
                 false,
-                buffer,
-                Some(channel),
+                capture,
+                sink,
+                listener_ctx,
             )
         });
 
-        let buffer = output.clone();
+        let sink = OutputSink {
+            buffer: output.clone(),
+            log_file: log_file.clone(),
+        };
         let stderr = child.stderr.take().unwrap();
+        let capture = output_options.streams.stderr();
+        let listener_ctx = ctx.clone();
         let _stderr_handle = thread::spawn(move || {
-            output_listener(BufReader::new(stderr), job_id, pid, true, buffer, None)
+            output_listener(
+                BufReader::new(stderr),
+                job_id,
+                pid,
+                true,
+                capture,
+                sink,
+                listener_ctx,
+            )
         });
 
         let info = ProcInfo {
@@ -173,6 +623,8 @@ impl Runner {
             state: ProcStatus::Spawned,
             start: Local::now(),
             end: None,
+            run,
+            manual: false,
             cpu: 0.0,
             memory: 0,
             virtual_memory: 0,
@@ -185,39 +637,133 @@ impl Runner {
         let child_proc = Runner {
             proc: child,
             info,
+            env: env.to_vec(),
+            cwd: cwd.map(Path::to_path_buf),
+            output_options,
+            effective_env: ctx.record_env.then(|| ctx.effective_env(job_id, env)),
             restart_info,
             user_terminated: false,
+            paused: false,
+            held: false,
             output,
+            log_file,
         };
+        ctx.audit(AuditEvent::Spawn, &child_proc.info, None);
         Ok(child_proc)
     }
+    /// Add termination marker to output buffer and log file.
+    /// The log file is synced to disk, to keep the final lines for crash investigations.
+    pub fn push_terminated(&self, exit_code: Option<i32>) {
+        let marker = LogLine::terminated(self.info.job_id, self.info.pid, self.info.run, exit_code);
+        if let Some(file) = &self.log_file {
+            if let Err(e) = write_log_file(file, &marker.file_line(), true) {
+                error!("Failed to write log file of job {}: {e}", self.info.job_id);
+            }
+        }
+        if let Ok(mut output) = self.output.lock() {
+            output.push(marker);
+        }
+    }
+    /// Run command to completion without capturing output and return its success.
+    /// The command is killed when it exceeds `timeout`.
+    pub fn check(
+        args: &[String],
+        timeout: Option<Duration>,
+        ctx: &RunnerContext,
+    ) -> Result<bool, DispatcherError> {
+        let Some((exe, cmd)) = args.split_first() else {
+            return Err(DispatcherError::EmptyProcCommandError);
+        };
+        ctx.check_allowed(exe)?;
+        let mut child = ctx
+            .command(exe)
+            .args(cmd)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(spawn_error)?;
+        let Some(timeout) = timeout else {
+            let status = child.wait().map_err(DispatcherError::ProcSpawnError)?;
+            return Ok(status.success());
+        };
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait().map_err(DispatcherError::ProcSpawnError)? {
+                return Ok(status.success());
+            }
+            if start.elapsed() > timeout {
+                child.kill().map_err(DispatcherError::KillError)?;
+                let _ = child.wait();
+                return Err(DispatcherError::CommandTimeoutError(timeout));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
     pub fn update_proc_state(&mut self) -> &ProcInfo {
         if self.info.end.is_none() {
             self.info.state = match self.proc.try_wait() {
                 Ok(Some(status)) if status.success() => ProcStatus::ExitOk,
                 Ok(Some(status)) => ProcStatus::ExitErr(status.code().unwrap_or(0)),
+                Ok(None) if self.paused => ProcStatus::Paused,
+                Ok(None) if Local::now() - self.info.start < STARTUP_WINDOW => ProcStatus::Starting,
                 Ok(None) => ProcStatus::Running,
                 Err(e) => ProcStatus::Unknown(e.to_string()),
             };
         }
         &self.info
     }
+    /// Exit code of terminated process (see [`exit_status_code`])
+    pub fn exit_code(&mut self) -> Option<i32> {
+        self.proc
+            .try_wait()
+            .ok()
+            .flatten()
+            .map(|status| exit_status_code(&status))
+    }
     pub fn is_running(&mut self) -> bool {
         !self.update_proc_state().state.exited()
     }
+    /// Suspend or continue process and its descendant processes
+    pub fn pause(&mut self, pause: bool) -> Result<(), DispatcherError> {
+        let signal = if pause {
+            Signal::Stop
+        } else {
+            Signal::Continue
+        };
+        self.signal(signal)?;
+        self.paused = pause;
+        Ok(())
+    }
+    /// Send signal to process and its descendant processes
+    pub fn signal(&self, signal: Signal) -> Result<(), DispatcherError> {
+        let pid = sysinfo::Pid::from_u32(self.proc.id());
+        let system = System::new_with_specifics(
+            RefreshKind::new().with_processes(ProcessRefreshKind::new()),
+        );
+        for pid in [pid].into_iter().chain(descendant_processes(&system, pid)) {
+            if let Some(process) = system.process(pid) {
+                info!("Sending {signal} to process {pid}");
+                // Not supported on Windows
+                if process.kill_with(signal).is_none() {
+                    return Err(DispatcherError::SignalNotSupportedError(signal));
+                }
+            }
+        }
+        Ok(())
+    }
     pub fn terminate(&mut self) -> Result<(), std::io::Error> {
         if self.info.program() == "just" {
             // just does not propagate signals, so we have to kill its child process
-            let just_pid = self.proc.id() as usize;
+            let just_pid = sysinfo::Pid::from_u32(self.proc.id());
             let system = System::new_with_specifics(
                 RefreshKind::new().with_processes(ProcessRefreshKind::new()),
             );
-            if let Some((pid, process)) = system.processes().iter().find(|(_pid, process)| {
-                process.parent().unwrap_or(0.into()) == just_pid.into()
-                    && process.name() != "ctrl-c"
-            }) {
-                info!("Terminating process {pid} (parent process {just_pid})");
-                process.kill(); // process.kill_with(Signal::Term)
+            for pid in child_processes(&system, just_pid) {
+                if let Some(process) = system.process(pid) {
+                    info!("Terminating process {pid} (parent process {just_pid})");
+                    process.kill(); // process.kill_with(Signal::Term)
+                }
             }
             // In an interactive terminal session, sending Ctrl-C terminates the running process.
             // let mut stdin = self.proc.stdin.take().unwrap();
@@ -228,57 +774,413 @@ impl Runner {
         }
         Ok(())
     }
+    /// Terminate process and all its descendant processes
+    ///
+    /// Catches grandchildren which outlive their parent, e.g. daemonized processes.
+    pub fn terminate_tree(&mut self) -> Result<(), std::io::Error> {
+        let pid = sysinfo::Pid::from_u32(self.proc.id());
+        let system = System::new_with_specifics(
+            RefreshKind::new().with_processes(ProcessRefreshKind::new()),
+        );
+        // Collect descendants before killing, since orphans are reparented
+        let descendants = descendant_processes(&system, pid);
+        for pid in descendants.into_iter().rev() {
+            if let Some(process) = system.process(pid) {
+                info!("Terminating descendant process {pid}");
+                process.kill();
+            }
+        }
+        info!("Terminating process {pid}");
+        self.proc.kill()
+    }
+    /// Send SIGTERM and kill processes still running after the grace period
+    ///
+    /// Falls back to [`Runner::terminate`] or [`Runner::terminate_tree`], if signals are not supported.
+    pub fn terminate_gracefully(
+        &mut self,
+        grace_period: Duration,
+        tree: bool,
+    ) -> Result<(), std::io::Error> {
+        self.send_term(tree)?.wait(grace_period);
+        // Reap own child process, which would remain as zombie otherwise
+        self.is_running();
+        Ok(())
+    }
+    /// Send SIGTERM without waiting for termination
+    ///
+    /// Processes are terminated immediately, if signals are not supported.
+    pub fn send_term(&mut self, tree: bool) -> Result<Termination, std::io::Error> {
+        let pid = sysinfo::Pid::from_u32(self.proc.id());
+        let system = System::new_with_specifics(
+            RefreshKind::new().with_processes(ProcessRefreshKind::new()),
+        );
+        let targets = if tree {
+            [pid]
+                .into_iter()
+                .chain(descendant_processes(&system, pid))
+                .collect()
+        } else if self.info.program() == "just" {
+            // just does not propagate signals, so we have to signal its child process
+            child_processes(&system, pid)
+        } else {
+            vec![pid]
+        };
+        let mut signaled = Vec::new();
+        for target in targets {
+            if let Some(process) = system.process(target) {
+                info!("Sending {} to process {target}", Signal::Term);
+                // Not supported on Windows
+                if process.kill_with(Signal::Term).is_none() {
+                    if tree {
+                        self.terminate_tree()?;
+                    } else {
+                        self.terminate()?;
+                    }
+                    signaled.clear();
+                    break;
+                }
+                signaled.push(target);
+            }
+        }
+        Ok(Termination { system, signaled })
+    }
+}
+
+/// Processes signaled with SIGTERM, which are killed when still running after the grace period
+pub struct Termination {
+    system: System,
+    signaled: Vec<sysinfo::Pid>,
+}
+
+impl Termination {
+    /// Wait for processes to exit and kill them after the grace period
+    pub fn wait(mut self, grace_period: Duration) {
+        let start = Instant::now();
+        while !self.signaled.is_empty() && start.elapsed() < grace_period {
+            thread::sleep(Duration::from_millis(50));
+            self.system
+                .refresh_processes(ProcessesToUpdate::Some(&self.signaled), true);
+            let system = &self.system;
+            // Exited child processes remain as zombies until reaped by their runner
+            self.signaled.retain(|pid| {
+                system
+                    .process(*pid)
+                    .is_some_and(|process| process.status() != ProcessStatus::Zombie)
+            });
+        }
+        for pid in self.signaled {
+            if let Some(process) = self.system.process(pid) {
+                info!("Killing process {pid} after grace period of {grace_period:?}");
+                process.kill();
+            }
+        }
+    }
+    /// Wait for all terminations within a common grace period
+    pub fn wait_all(terminations: Vec<Termination>, grace_period: Duration) {
+        thread::scope(|scope| {
+            for termination in terminations {
+                scope.spawn(move || termination.wait(grace_period));
+            }
+        });
+    }
 }
 
 impl Drop for Runner {
     fn drop(&mut self) {
-        self.terminate().ok();
+        if self.is_running() {
+            self.terminate_gracefully(DEFAULT_GRACE_PERIOD, false).ok();
+        }
+    }
+}
+
+/// Time between SIGTERM and SIGKILL when stopping a process
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Replace `${VAR}` placeholders with values of `vars` (empty if undefined).
+/// `${VAR:-default}` uses the default value if `VAR` is undefined or empty.
+pub fn interpolate_env(value: &str, vars: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..start + 2 + len];
+        let (name, default) = match placeholder.split_once(":-") {
+            Some((name, default)) => (name, default),
+            None => (placeholder, ""),
+        };
+        match vars.get(name).filter(|value| !value.is_empty()) {
+            Some(value) => result.push_str(value),
+            None => result.push_str(default),
+        }
+        rest = &rest[start + 2 + len + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Signal names without `SIG` prefix
+const SIGNAL_NAMES: [(&str, Signal); 16] = [
+    ("HUP", Signal::Hangup),
+    ("INT", Signal::Interrupt),
+    ("QUIT", Signal::Quit),
+    ("ABRT", Signal::Abort),
+    ("KILL", Signal::Kill),
+    ("USR1", Signal::User1),
+    ("USR2", Signal::User2),
+    ("ALRM", Signal::Alarm),
+    ("TERM", Signal::Term),
+    ("CHLD", Signal::Child),
+    ("CONT", Signal::Continue),
+    ("STOP", Signal::Stop),
+    ("TSTP", Signal::TSTP),
+    ("TTIN", Signal::TTIN),
+    ("TTOU", Signal::TTOU),
+    ("WINCH", Signal::Winch),
+];
+
+/// Platform specific signal numbers
+#[cfg(unix)]
+const SIGNAL_NUMBERS: [(i32, Signal); 16] = [
+    (libc::SIGHUP, Signal::Hangup),
+    (libc::SIGINT, Signal::Interrupt),
+    (libc::SIGQUIT, Signal::Quit),
+    (libc::SIGABRT, Signal::Abort),
+    (libc::SIGKILL, Signal::Kill),
+    (libc::SIGUSR1, Signal::User1),
+    (libc::SIGUSR2, Signal::User2),
+    (libc::SIGALRM, Signal::Alarm),
+    (libc::SIGTERM, Signal::Term),
+    (libc::SIGCHLD, Signal::Child),
+    (libc::SIGCONT, Signal::Continue),
+    (libc::SIGSTOP, Signal::Stop),
+    (libc::SIGTSTP, Signal::TSTP),
+    (libc::SIGTTIN, Signal::TTIN),
+    (libc::SIGTTOU, Signal::TTOU),
+    (libc::SIGWINCH, Signal::Winch),
+];
+#[cfg(not(unix))]
+const SIGNAL_NUMBERS: [(i32, Signal); 0] = [];
+
+/// Parse signal name (`HUP` or `SIGHUP`, case insensitive) or number.
+/// Signals which can't be sent on this platform are rejected.
+pub fn parse_signal(s: &str) -> Result<Signal, DispatcherError> {
+    let upper = s.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    let signal = if let Ok(number) = s.parse::<i32>() {
+        SIGNAL_NUMBERS
+            .iter()
+            .find(|(num, _)| *num == number)
+            .map(|(_, signal)| *signal)
+    } else {
+        SIGNAL_NAMES
+            .iter()
+            .find(|(signal_name, _)| *signal_name == name)
+            .map(|(_, signal)| *signal)
+    }
+    .ok_or(DispatcherError::InvalidSignalError(s.to_string()))?;
+    if !sysinfo::SUPPORTED_SIGNALS.contains(&signal) {
+        return Err(DispatcherError::SignalNotSupportedError(signal));
+    }
+    Ok(signal)
+}
+
+/// Exit code of a process.
+/// Termination by a signal is reported as `128 + signal`, like in shells.
+pub fn exit_status_code(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Too many open files in process (EMFILE) or system (ENFILE)
+#[cfg(unix)]
+const FILE_LIMIT_ERRORS: [i32; 2] = [24, 23];
+#[cfg(not(unix))]
+const FILE_LIMIT_ERRORS: [i32; 0] = [];
+
+fn spawn_error(e: std::io::Error) -> DispatcherError {
+    if e.raw_os_error()
+        .is_some_and(|code| FILE_LIMIT_ERRORS.contains(&code))
+    {
+        DispatcherError::ResourceExhaustedError(e)
+    } else {
+        DispatcherError::ProcSpawnError(e)
     }
 }
 
 fn output_listener<R: Read>(
-    reader: BufReader<R>,
+    mut reader: BufReader<R>,
     job_id: JobId,
     pid: Pid,
     is_stderr: bool,
-    buffer: Arc<Mutex<OutputBuffer>>,
-    channel: Option<mpsc::Sender<Pid>>,
+    capture: bool,
+    sink: OutputSink,
+    ctx: RunnerContext,
 ) {
-    reader.lines().map_while(Result::ok).for_each(|line| {
-        let ts = Local::now();
+    let mut batch: Vec<(DateTime<Local>, String)> = Vec::new();
+    loop {
+        let mut line = String::new();
+        if !matches!(reader.read_line(&mut line), Ok(n) if n > 0) {
+            break;
+        }
+        // Output of streams which are not captured is drained, to prevent blocking of the process
+        if !capture {
+            continue;
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
         if is_stderr {
             eprintln!("[{job_id}|{pid}] {line}");
         } else {
             println!("[[{job_id}|{pid}] {line}");
         }
-        if let Ok(mut buffer) = buffer.lock() {
+        batch.push((Local::now(), line));
+        // Lines already read from the pipe are added with a single lock of the buffer,
+        // which reduces contention with log readers for processes with high output rates
+        if !reader.buffer().contains(&b'\n') {
+            append_lines(&sink, &mut batch, job_id, pid, is_stderr, &ctx);
+        }
+    }
+    append_lines(&sink, &mut batch, job_id, pid, is_stderr, &ctx);
+    // Termination is reported by stdout listener.
+    // The child watcher adds the termination marker including the exit code.
+    if !is_stderr {
+        ctx.notify_exit(pid);
+    }
+}
+
+/// Move timestamped lines into output buffer and append them to the log file
+fn append_lines(
+    sink: &OutputSink,
+    lines: &mut Vec<(DateTime<Local>, String)>,
+    job_id: JobId,
+    pid: Pid,
+    is_stderr: bool,
+    ctx: &RunnerContext,
+) {
+    if lines.is_empty() {
+        return;
+    }
+    let mut file_lines = String::new();
+    if let Ok(mut buffer) = sink.buffer.lock() {
+        for (ts, line) in lines.drain(..) {
+            buffer.stats.add(&line);
             let entry = LogLine {
                 ts,
                 job_id,
                 pid,
+                run: buffer.run,
                 is_stderr,
                 line,
             };
+            if sink.log_file.is_some() {
+                file_lines.push_str(&entry.file_line());
+            }
             buffer.push(entry);
         }
-    });
-    if let Some(channel) = channel {
-        let ts = Local::now();
-        if let Ok(mut buffer) = buffer.lock() {
-            let entry = LogLine {
-                ts,
-                job_id,
-                pid,
-                is_stderr,
-                line: "<process terminated>".to_string(),
-            };
-            buffer.push(entry);
+    }
+    if let Some(file) = &sink.log_file {
+        // Write errors are logged only, to keep draining the process output
+        if let Err(e) = write_log_file(file, &file_lines, false) {
+            error!("Failed to write log file of job {job_id}: {e}");
         }
-        // Notify watcher
-        channel.send(pid).unwrap();
+    }
+    if let Some(budget) = ctx.log_budget.as_ref().filter(|budget| budget.exceeded()) {
+        budget.enforce();
     }
 }
 
+/// Append lines to a log file and flush them, so that they survive a crash of the background process.
+/// With `sync`, the data is also persisted on disk.
+fn write_log_file(file: &Mutex<File>, lines: &str, sync: bool) -> Result<(), std::io::Error> {
+    let mut file = file.lock().expect("lock");
+    file.write_all(lines.as_bytes())?;
+    file.flush()?;
+    if sync {
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+/// Full path of an executable, searched in `PATH` if `program` has no path component
+pub fn resolve_executable(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let candidates = |dir: PathBuf| {
+        let exe = dir.join(program);
+        [exe.with_extension(env::consts::EXE_EXTENSION), exe]
+    };
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .flat_map(candidates)
+            .find(|exe| exe.is_file())
+    })
+}
+
+/// Check program against allowlist entries.
+/// Names match programs called without path, absolute paths match the resolved executable.
+fn is_allowed_command(allowed: &[String], program: &str) -> bool {
+    let Some(exe) = resolve_executable(program) else {
+        return false;
+    };
+    let by_name = Path::new(program).components().count() == 1;
+    allowed.iter().any(|entry| {
+        let entry_path = Path::new(entry);
+        if entry_path.is_absolute() {
+            exe == entry_path
+        } else {
+            by_name && entry == program
+        }
+    })
+}
+
+/// Child processes of `parent`, excluding threads (e.g. the `ctrl-c` handler thread of `just`)
+pub fn child_processes(system: &System, parent: sysinfo::Pid) -> Vec<sysinfo::Pid> {
+    system
+        .processes()
+        .iter()
+        .filter(|(_pid, process)| {
+            process.parent() == Some(parent) && process.thread_kind().is_none()
+        })
+        .map(|(pid, _process)| *pid)
+        .collect()
+}
+
+/// All descendant processes of `parent`
+pub fn descendant_processes(system: &System, parent: sysinfo::Pid) -> Vec<sysinfo::Pid> {
+    let mut pids = child_processes(system, parent);
+    let mut idx = 0;
+    while idx < pids.len() {
+        pids.extend(child_processes(system, pids[idx]));
+        idx += 1;
+    }
+    pids
+}
+
+/// Main process of a process tree.
+///
+/// Follows the chain of single child processes (e.g. `just` -> shebang interpreter)
+/// down to the deepest process. A process with multiple children is considered
+/// to be the main process.
+pub fn main_process(system: &System, pid: sysinfo::Pid) -> sysinfo::Pid {
+    let mut main_pid = pid;
+    while let [child_pid] = child_processes(system, main_pid)[..] {
+        main_pid = child_pid;
+    }
+    main_pid
+}
+
 /// Current user
 pub fn get_user_name() -> Option<String> {
     let system = System::new_with_specifics(
@@ -296,3 +1198,89 @@ pub fn get_user_name() -> Option<String> {
                 .map(|user| user.name().to_string())
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate_placeholders() {
+        let vars = HashMap::from([
+            ("PORT".to_string(), "8080".to_string()),
+            ("HOST".to_string(), "localhost".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+        assert_eq!(interpolate_env("no vars", &vars), "no vars");
+        assert_eq!(interpolate_env("${HOST}:${PORT}", &vars), "localhost:8080");
+        assert_eq!(interpolate_env("--port=${PORT}/", &vars), "--port=8080/");
+        assert_eq!(interpolate_env("x${UNDEFINED}y", &vars), "xy");
+        // Default values
+        assert_eq!(interpolate_env("${PORT:-3000}", &vars), "8080");
+        assert_eq!(interpolate_env("${UNDEFINED:-3000}", &vars), "3000");
+        assert_eq!(interpolate_env("${EMPTY:-3000}", &vars), "3000");
+        assert_eq!(interpolate_env("${UNDEFINED:-}", &vars), "");
+        assert_eq!(interpolate_env("${UNDEFINED:-a:-b}", &vars), "a:-b");
+        // Unterminated placeholders and plain `$` are kept
+        assert_eq!(interpolate_env("${PORT", &vars), "${PORT");
+        assert_eq!(interpolate_env("${PORT}${HOST", &vars), "8080${HOST");
+        assert_eq!(interpolate_env("$PORT $", &vars), "$PORT $");
+    }
+
+    #[test]
+    fn sanitize_control_characters() {
+        assert!(matches!(sanitize_line("plain text"), Cow::Borrowed(_)));
+        // Tabs and non-ASCII text are kept
+        assert_eq!(sanitize_line("a\tb äöü 日本"), "a\tb äöü 日本");
+        assert_eq!(
+            sanitize_line("\x1b[31mred\x1b[0m"),
+            "\\u{1b}[31mred\\u{1b}[0m"
+        );
+        assert_eq!(sanitize_line("a\rb"), "a\\rb");
+        assert_eq!(sanitize_line("bell\x07"), "bell\\u{7}");
+        assert_eq!(sanitize_line("del\x7f"), "del\\u{7f}");
+        // C1 control character
+        assert_eq!(sanitize_line("\u{9b}2J"), "\\u{9b}2J");
+        // Invisible formatting characters
+        assert_eq!(sanitize_line("ab\u{200b}c"), "ab\\u{200b}c");
+        assert_eq!(sanitize_line("\u{202e}txt.exe"), "\\u{202e}txt.exe");
+        assert_eq!(sanitize_line("\u{feff}bom"), "\\u{feff}bom");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signal_names_and_numbers() {
+        assert_eq!(parse_signal("HUP").unwrap(), Signal::Hangup);
+        assert_eq!(parse_signal("SIGHUP").unwrap(), Signal::Hangup);
+        assert_eq!(parse_signal("sigusr1").unwrap(), Signal::User1);
+        assert_eq!(parse_signal("term").unwrap(), Signal::Term);
+        assert_eq!(parse_signal("9").unwrap(), Signal::Kill);
+        assert_eq!(parse_signal("15").unwrap(), Signal::Term);
+    }
+
+    #[test]
+    fn invalid_signals() {
+        assert!(matches!(
+            parse_signal("NOPE"),
+            Err(DispatcherError::InvalidSignalError(_))
+        ));
+        assert!(parse_signal("").is_err());
+        assert!(parse_signal("SIG").is_err());
+        // Prefix is only removed from names
+        assert!(parse_signal("SIG9").is_err());
+        assert!(parse_signal("-9").is_err());
+        assert!(parse_signal("999").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_code_of_killed_process() {
+        let exit_code = |script: &str| {
+            let status = Command::new("sh").args(["-c", script]).status().unwrap();
+            exit_status_code(&status)
+        };
+        assert_eq!(exit_code("exit 0"), 0);
+        assert_eq!(exit_code("exit 3"), 3);
+        assert_eq!(exit_code("kill -9 $$"), 128 + libc::SIGKILL);
+        assert_eq!(exit_code("kill -SEGV $$"), 128 + libc::SIGSEGV);
+    }
+}